@@ -9,7 +9,7 @@ where
     if col_width == 0 {
         return Ok(());
     }
-    let cols = max_width / col_width;
+    let cols = (max_width / col_width).max(1);
     for (i, item) in items.iter().enumerate() {
         write!(out, "{}", item.as_ref())?;
         for _ in 0..col_width - item.as_ref().len() {
@@ -19,6 +19,37 @@ where
             writeln!(out)?;
         }
     }
+    if !items.len().is_multiple_of(cols) {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes rows as a table with every column padded to the width of its widest cell
+pub fn write_table<T, U>(out: &mut T, rows: &[Vec<U>]) -> io::Result<()>
+where
+    T: Write,
+    U: AsRef<str>,
+{
+    let mut col_widths = vec![];
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            if i == col_widths.len() {
+                col_widths.push(0);
+            }
+            col_widths[i] = col_widths[i].max(cell.as_ref().chars().count());
+        }
+    }
+    for row in rows {
+        let mut line = String::new();
+        for (cell, col_width) in row.iter().zip(&col_widths) {
+            line.push_str(cell.as_ref());
+            for _ in cell.as_ref().chars().count()..col_width + 2 {
+                line.push(' ');
+            }
+        }
+        writeln!(out, "{}", line.trim_end())?;
+    }
     Ok(())
 }
 
@@ -28,6 +59,19 @@ where T: AsRef<str> {
     write(&mut stdout(), items, cols).unwrap();
 }
 
+/// Prints a table whose first row is a header
+pub fn print_table<T>(header: &[&str], rows: &[Vec<T>])
+where T: AsRef<str> {
+    let mut table = vec![header.iter().map(|cell| cell.to_string()).collect::<Vec<_>>()];
+    table.extend(rows.iter().map(|row| row.iter().map(|cell| cell.as_ref().to_owned()).collect()));
+    let mut buf = vec![];
+    write_table(&mut buf, &table).unwrap();
+    let buf = String::from_utf8(buf).unwrap();
+    let (header, rows) = buf.split_once('\n').unwrap();
+    println!("{}", crate::styled(header, crate::HEADER_STYLE));
+    print!("{}", rows);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -46,4 +90,18 @@ mod tests {
             "one   two   three four  five  \nsix   seven eight nine  ten   \n"
         );
     }
+
+    #[test]
+    fn test_write_table() {
+        let mut buf = vec![];
+        write_table(
+            &mut buf,
+            &[vec!["Package", "Version"], vec!["meow-config", "1.0.0-1"], vec!["sh", "5.2-1"]],
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(buf.as_slice()).unwrap(),
+            "Package      Version\nmeow-config  1.0.0-1\nsh           5.2-1\n"
+        );
+    }
 }
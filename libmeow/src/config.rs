@@ -0,0 +1,287 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{Context, bail};
+use glob::Pattern;
+
+use crate::repo::{CACHE_DIR, Repository};
+use crate::{meowdb, path_chroot};
+
+pub const CONFIG_PATH: &str = "/etc/meow/meow.conf";
+
+/// Settings read from `CONFIG_PATH`, a file made of `[section]` headers followed by
+/// `Key = Value` lines. The `[options]` section holds general settings and every other section
+/// configures the repository it is named after.
+pub struct Config {
+    /// The file the settings were read from
+    pub path: PathBuf,
+    /// The package database, inside the root once loaded
+    pub db_path: PathBuf,
+    /// Where repository indexes and packages are downloaded to, inside the root once loaded
+    pub cache_dir: PathBuf,
+    /// Which repositories must have their indexes signed
+    pub sig_level: SigLevel,
+    /// Shell that runs package hooks
+    pub hook_shell: PathBuf,
+    pub color: ColorMode,
+    /// Repositories in the order they are searched
+    pub repositories: Vec<Repository>,
+    /// Packages that are never upgraded
+    pub hold: Vec<String>,
+    /// Files that are never overwritten once they exist, new versions are installed next to them
+    /// as `.meownew` files instead
+    pub no_upgrade: Vec<Pattern>,
+    /// Files and symlinks that are never extracted
+    pub no_extract: Vec<Pattern>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SigLevel {
+    /// Every repository needs a `PublicKey`
+    Required,
+    /// Repositories with a remote `Server` need a `PublicKey`
+    #[default]
+    Remote,
+    /// Signatures are not checked and `PublicKey` is ignored
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorMode {
+    /// Colour output written to a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            path: PathBuf::from(CONFIG_PATH),
+            db_path: PathBuf::from(meowdb::DB_PATH),
+            cache_dir: PathBuf::from(CACHE_DIR),
+            sig_level: SigLevel::default(),
+            hook_shell: PathBuf::from("/usr/bin/bash"),
+            color: ColorMode::default(),
+            repositories: vec![],
+            hold: vec![],
+            no_upgrade: vec![],
+            no_extract: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Reads `path`, or the configuration file of the root if it is `None`. The configuration
+    /// file of the root may be missing, which is the same as an empty one.
+    pub fn load(root: &Path, path: Option<&Path>) -> eyre::Result<Config> {
+        let (path, optional) = match path {
+            Some(path) => (path.to_owned(), false),
+            None => (path_chroot(CONFIG_PATH, root), true),
+        };
+        let mut config = if optional && !fs::exists(&path)? {
+            Config::default()
+        } else {
+            let text = fs::read_to_string(&path).with_context(|| {
+                format!("Failed to read configuration file `{}`", path.display())
+            })?;
+            Config::parse(&text)
+                .with_context(|| format!("Invalid configuration file `{}`", path.display()))?
+        };
+        config.db_path = path_chroot(&config.db_path, root);
+        config.cache_dir = path_chroot(&config.cache_dir, root);
+        config.path = path;
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> eyre::Result<Config> {
+        let mut config = Config::default();
+        let mut section = None;
+        for (i, line) in text.lines().enumerate() {
+            let lineno = i + 1;
+            let line = line.split_once('#').map(|(line, _comment)| line).unwrap_or(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let Some(name) = name.strip_suffix(']') else {
+                    bail!("line {}: expected `]` at the end of the section header", lineno);
+                };
+                let name = name.trim();
+                if name.is_empty() {
+                    bail!("line {}: section name is empty", lineno);
+                }
+                if name != "options" {
+                    if config.repositories.iter().any(|repo| repo.name == name) {
+                        bail!("line {}: repository `{}` is defined more than once", lineno, name);
+                    }
+                    config.repositories.push(Repository::new(name.to_owned()));
+                }
+                section = Some(name.to_owned());
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("line {}: expected `Key = Value`", lineno);
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match section.as_deref() {
+                None => bail!("line {}: `{}` must be inside a section", lineno, key),
+                Some("options") => match key {
+                    "HoldPkg" => config.hold.extend(value.split_whitespace().map(str::to_owned)),
+                    "NoUpgrade" => config.no_upgrade.extend(parse_globs(value, lineno)?),
+                    "NoExtract" => config.no_extract.extend(parse_globs(value, lineno)?),
+                    "DBPath" => config.db_path = parse_path(key, value, lineno)?,
+                    "CacheDir" => config.cache_dir = parse_path(key, value, lineno)?,
+                    "HookShell" => config.hook_shell = parse_path(key, value, lineno)?,
+                    "SigLevel" => {
+                        config.sig_level = match value {
+                            "Required" => SigLevel::Required,
+                            "Remote" => SigLevel::Remote,
+                            "Never" => SigLevel::Never,
+                            _ => bail!(
+                                "line {}: `SigLevel` must be `Required`, `Remote` or `Never`",
+                                lineno
+                            ),
+                        }
+                    }
+                    "Color" => {
+                        config.color = match value {
+                            "auto" => ColorMode::Auto,
+                            "always" => ColorMode::Always,
+                            "never" => ColorMode::Never,
+                            _ => bail!(
+                                "line {}: `Color` must be `auto`, `always` or `never`",
+                                lineno
+                            ),
+                        }
+                    }
+                    _ => bail!("line {}: unknown option `{}`", lineno, key),
+                },
+                Some(_) => {
+                    let repo = config.repositories.last_mut().unwrap();
+                    match key {
+                        "Server" => {
+                            if !["file://", "http://", "https://"]
+                                .iter()
+                                .any(|scheme| value.starts_with(scheme))
+                            {
+                                bail!("line {}: unsupported server URL `{}`", lineno, value);
+                            }
+                            repo.servers.push(value.to_owned());
+                        }
+                        "PublicKey" => repo.publickey = Some(value.to_owned()),
+                        _ => bail!("line {}: unknown repository setting `{}`", lineno, key),
+                    }
+                }
+            }
+        }
+        for repo in &mut config.repositories {
+            if repo.servers.is_empty() {
+                bail!("repository `{}` has no `Server`", repo.name);
+            }
+            match config.sig_level {
+                SigLevel::Required if repo.publickey.is_none() => {
+                    bail!(
+                        "repository `{}` has no `PublicKey` but `SigLevel` is `Required`",
+                        repo.name
+                    )
+                }
+                SigLevel::Remote
+                    if repo.publickey.is_none()
+                        && repo.servers.iter().any(|server| !server.starts_with("file://")) =>
+                {
+                    bail!("repository `{}` has a remote `Server` but no `PublicKey`", repo.name)
+                }
+                // Indexes are only verified against a public key
+                SigLevel::Never => repo.publickey = None,
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_path(key: &str, value: &str, lineno: usize) -> eyre::Result<PathBuf> {
+    if !value.starts_with('/') {
+        bail!("line {}: `{}` must be an absolute path", lineno, key);
+    }
+    Ok(PathBuf::from(value))
+}
+
+fn parse_globs(value: &str, lineno: usize) -> eyre::Result<Vec<Pattern>> {
+    let mut globs = vec![];
+    for glob in value.split_whitespace() {
+        let Ok(pattern) = Pattern::new(glob) else {
+            bail!("line {}: invalid glob `{}`", lineno, glob);
+        };
+        globs.push(pattern);
+    }
+    Ok(globs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "# meow\n[options]\nHoldPkg = linux glibc\nNoUpgrade = /etc/ssh/sshd_config\nNoExtract = /usr/share/locale/* /usr/share/man/*\n\n[core]\nServer = file:///srv/core # local\nPublicKey = RWQ\n",
+        )
+        .unwrap();
+        assert_eq!(config.hold, ["linux", "glibc"]);
+        assert!(config.no_upgrade[0].matches("/etc/ssh/sshd_config"));
+        assert_eq!(config.no_extract.len(), 2);
+        assert!(config.no_extract[0].matches("/usr/share/locale/de/LC_MESSAGES/meow.mo"));
+        assert_eq!(config.repositories.len(), 1);
+        assert_eq!(config.repositories[0].name, "core");
+        assert_eq!(config.repositories[0].servers, ["file:///srv/core"]);
+        assert_eq!(config.repositories[0].publickey.as_deref(), Some("RWQ"));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let config = Config::parse(
+            "[options]\nDBPath = /srv/meow.db\nCacheDir = /srv/cache\nHookShell = /bin/sh\nSigLevel = Never\nColor = never\n[core]\nServer = https://srv/core\nPublicKey = RWQ\n",
+        )
+        .unwrap();
+        assert_eq!(config.db_path, Path::new("/srv/meow.db"));
+        assert_eq!(config.cache_dir, Path::new("/srv/cache"));
+        assert_eq!(config.hook_shell, Path::new("/bin/sh"));
+        assert_eq!(config.sig_level, SigLevel::Never);
+        assert_eq!(config.color, ColorMode::Never);
+        assert_eq!(config.repositories[0].publickey, None);
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.db_path, Path::new(meowdb::DB_PATH));
+        assert_eq!(config.sig_level, SigLevel::Remote);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = Config::parse("[core]\nServer = file:///srv/core\nMirror = x\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 3: unknown repository setting `Mirror`");
+        let error = Config::parse("Server = file:///srv/core\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 1: `Server` must be inside a section");
+        let error = Config::parse("[core]\nServer = ftp://srv/core\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: unsupported server URL `ftp://srv/core`");
+        let error = Config::parse("[core]\nServer = https://srv/core\n").err().unwrap();
+        assert_eq!(error.to_string(), "repository `core` has a remote `Server` but no `PublicKey`");
+        let error = Config::parse("[options]\nNoExtract = /usr/[share\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: invalid glob `/usr/[share`");
+        let error = Config::parse("[options]\nDBPath = var/lib/meow.db\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: `DBPath` must be an absolute path");
+        let error = Config::parse("\n[options]\nColor = yes\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 3: `Color` must be `auto`, `always` or `never`");
+        let error =
+            Config::parse("[options]\nSigLevel = Required\n[core]\nServer = file:///srv/core\n")
+                .err()
+                .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "repository `core` has no `PublicKey` but `SigLevel` is `Required`"
+        );
+        let error = Config::parse("[core\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 1: expected `]` at the end of the section header");
+    }
+}
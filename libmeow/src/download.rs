@@ -0,0 +1,89 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use eyre::{Context, bail};
+use ureq::Agent;
+
+use crate::progress::Progress;
+
+/// Downloads at least this big show a progress bar
+const PROGRESS_MIN_SIZE: u64 = 1024 * 1024;
+
+/// How long a server may take to connect and to start sending its response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long receiving a single file may take, a download cut off by it is resumed the next time
+const BODY_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Downloads a URL to `dest`. The data goes to `dest` with an added `.part` extension until the
+/// download is complete, an interrupted download is resumed from there the next time.
+pub fn download(url: &str, dest: &Path) -> eyre::Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory `{}`", parent.display()))?;
+    }
+    let partpath = dest.with_added_extension("part");
+    let offset = fs::metadata(&partpath).map(|metadata| metadata.len()).unwrap_or(0);
+
+    let agent: Agent = Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_connect(Some(RESPONSE_TIMEOUT))
+        .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+        .timeout_recv_body(Some(BODY_TIMEOUT))
+        .build()
+        .into();
+    let mut request = agent.get(url);
+    if offset > 0 {
+        request = request.header("Range", format!("bytes={}-", offset));
+    }
+    let response = request.call().with_context(|| format!("Failed to download `{}`", url))?;
+    let mut file = match response.status().as_u16() {
+        200 => File::create(&partpath)?,
+        206 => {
+            let start = response
+                .headers()
+                .get("Content-Range")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("bytes "))
+                .and_then(|value| value.split_once('-'))
+                .map(|(start, _)| start);
+            if start != Some(offset.to_string().as_str()) {
+                bail!("Failed to download `{}`: server resumed at the wrong offset", url);
+            }
+            OpenOptions::new().append(true).open(&partpath)?
+        }
+        // The previous attempt got the whole file but did not get to rename it
+        416 if offset > 0 => {
+            fs::rename(&partpath, dest)?;
+            return Ok(());
+        }
+        status => bail!("Failed to download `{}`: HTTP status {}", url, status),
+    };
+
+    let filename = url.rsplit('/').next().unwrap_or(url);
+    let mut progress = response
+        .body()
+        .content_length()
+        .filter(|&length| length >= PROGRESS_MIN_SIZE)
+        .map(|length| Progress::new(format!("Downloading {}", filename), length));
+    let mut body = response.into_body().into_reader();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = body.read(&mut buf).with_context(|| format!("Failed to download `{}`", url))?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])?;
+        if let Some(progress) = &mut progress {
+            progress.advance(n as u64);
+        }
+    }
+    if let Some(progress) = &mut progress {
+        progress.finish();
+    }
+    drop(file);
+    fs::rename(&partpath, dest)?;
+    Ok(())
+}
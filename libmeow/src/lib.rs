@@ -1,21 +1,74 @@
 pub mod columned;
+pub mod config;
+pub mod download;
+pub mod lock;
 pub mod meowdb;
 pub mod meowzip;
+pub mod progress;
+pub mod repo;
+pub mod resolve;
+pub mod signature;
+pub mod version;
 
 use std::fs::{self, File};
 use std::hash::Hasher;
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use clap::builder::Styles;
-use clap::builder::styling::{AnsiColor, Effects};
+use clap::builder::styling::{AnsiColor, Effects, Style};
 use xxhash_rust::xxh3::Xxh3Default;
 
+use crate::config::ColorMode;
+
+pub const HEADER_STYLE: Style = AnsiColor::Green.on_default().effects(Effects::BOLD);
+pub const LITERAL_STYLE: Style = AnsiColor::Cyan.on_default().effects(Effects::BOLD);
+pub const PLACEHOLDER_STYLE: Style = AnsiColor::Cyan.on_default();
+
 pub const CLAP_STYLES: Styles = Styles::styled()
-    .header(AnsiColor::Green.on_default().effects(Effects::BOLD))
-    .usage(AnsiColor::Green.on_default().effects(Effects::BOLD))
-    .literal(AnsiColor::Cyan.on_default().effects(Effects::BOLD))
-    .placeholder(AnsiColor::Cyan.on_default());
+    .header(HEADER_STYLE)
+    .usage(HEADER_STYLE)
+    .literal(LITERAL_STYLE)
+    .placeholder(PLACEHOLDER_STYLE);
+
+static COLOR: OnceLock<ColorMode> = OnceLock::new();
+
+/// Sets when `use_color` and `styled` colour output, only the first call has an effect
+pub fn set_color(color: ColorMode) {
+    let _ = COLOR.set(color);
+}
+
+/// Whether output written to `stream` is coloured
+pub fn use_color(stream: impl IsTerminal) -> bool {
+    match COLOR.get().copied().unwrap_or_default() {
+        ColorMode::Auto => stream.is_terminal(),
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+    }
+}
+
+/// Applies `style` to `text`, unless colours are turned off or, by default, stdout is not a
+/// terminal
+pub fn styled(text: &str, style: Style) -> String {
+    if !use_color(io::stdout()) {
+        return text.to_owned();
+    }
+    format!("{}{}{}", style.render(), text, style.render_reset())
+}
+
+/// Asks a yes or no question on the terminal, answering nothing means yes
+pub fn confirm(question: &str) -> eyre::Result<bool> {
+    print!("{} [Y/n] ", styled(question, LITERAL_STYLE));
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        println!();
+        return Ok(false);
+    }
+    let answer = answer.trim();
+    Ok(answer.is_empty() || answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
 
 pub fn file_checksum(path: &Path) -> eyre::Result<u64> {
     let meta = fs::symlink_metadata(path)?;
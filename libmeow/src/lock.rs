@@ -0,0 +1,95 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+use eyre::{Context, bail};
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+
+/// Lock on the package database and the files it tracks, released when dropped. The lock file
+/// is next to the database, named after it with a `.lck` extension.
+pub struct Lock {
+    file: Option<Flock<File>>,
+    exclusive: bool,
+}
+
+/// Take the lock for an operation that modifies the system, `warn` is told about a lock that a
+/// process died holding
+pub fn exclusive(db_path: &Path, mut warn: impl FnMut(&str)) -> eyre::Result<Lock> {
+    let path = db_path.with_added_extension("lck");
+    fs::create_dir_all(path.parent().unwrap())?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .context("Failed to open the lock file")?;
+    let mut file = acquire(file, FlockArg::LockExclusiveNonblock, &path)?;
+    if let Some(pid) = read_pid(&mut file)?
+        && pid != Pid::this()
+        && !is_running(pid)
+    {
+        warn(&format!(
+            "removing stale lock left by process {}, the previous operation may not have completed",
+            pid
+        ));
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", Pid::this())?;
+    file.sync_all()?;
+    Ok(Lock { file: Some(file), exclusive: true })
+}
+
+/// Take the lock for an operation that only reads the database, nothing is created if no
+/// operation ever modified it
+pub fn shared(db_path: &Path) -> eyre::Result<Lock> {
+    let path = db_path.with_added_extension("lck");
+    if !fs::exists(&path)? {
+        return Ok(Lock { file: None, exclusive: false });
+    }
+    let file = File::open(&path).context("Failed to open the lock file")?;
+    let file = acquire(file, FlockArg::LockSharedNonblock, &path)?;
+    Ok(Lock { file: Some(file), exclusive: false })
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file
+            && self.exclusive
+        {
+            let _ = file.set_len(0);
+        }
+    }
+}
+
+fn acquire(file: File, arg: FlockArg, path: &Path) -> eyre::Result<Flock<File>> {
+    match Flock::lock(file, arg) {
+        Ok(file) => Ok(file),
+        Err((mut file, Errno::EWOULDBLOCK)) => match read_pid(&mut file)? {
+            Some(pid) if is_running(pid) => {
+                bail!(
+                    "Another meow-pkg process (PID {}) is holding the lock `{}`",
+                    pid,
+                    path.display()
+                )
+            }
+            _ => bail!("Another meow-pkg process is holding the lock `{}`", path.display()),
+        },
+        Err((_, errno)) => Err(errno).context("Failed to lock the lock file"),
+    }
+}
+
+fn read_pid(file: &mut File) -> eyre::Result<Option<Pid>> {
+    let mut buf = String::new();
+    file.rewind()?;
+    file.read_to_string(&mut buf)?;
+    Ok(buf.trim().parse().ok().map(Pid::from_raw))
+}
+
+fn is_running(pid: Pid) -> bool {
+    !matches!(kill(pid, None), Err(Errno::ESRCH))
+}
@@ -1,37 +1,184 @@
 use std::fs;
 use std::path::Path;
 
-use eyre::Context;
-use redb::{Database, TableDefinition};
+use eyre::{Context, bail};
+use redb::backends::InMemoryBackend;
+use redb::{
+    CacheStats,
+    Database,
+    Key,
+    ReadOnlyDatabase,
+    ReadOnlyTable,
+    ReadTransaction,
+    ReadableDatabase,
+    ReadableTable,
+    Table,
+    TableDefinition,
+    TableError,
+    TransactionError,
+    Value,
+    WriteTransaction,
+};
 
 use crate::meowzip::{MeowZipEntry, MeowZipMetadata};
-use crate::path_chroot;
 
-const DB_PATH: &str = "/var/lib/meow.db";
+/// Where the package database is unless the configuration file says otherwise
+pub const DB_PATH: &str = "/var/lib/meow.db";
 
-pub fn open(root: &Path) -> eyre::Result<redb::Database> {
-    let path = path_chroot(DB_PATH, root);
-    let uninitialized = !fs::exists(&path).context("Failed to open or create the database")?;
+/// Opens the database for a command that changes it, creating it if it does not exist yet
+pub fn open(path: &Path) -> eyre::Result<redb::Database> {
+    let uninitialized = !fs::exists(path).context("Failed to open or create the database")?;
     if uninitialized {
         fs::create_dir_all(path.parent().unwrap())?;
     }
     let db = Database::create(path).context("Failed to open or create the database")?;
     if uninitialized {
-        let write_txn = db.begin_write()?;
-        {
-            write_txn.open_table(PACKAGES)?;
-            write_txn.open_table(FILES)?;
-        }
-        write_txn.commit()?;
+        create_tables(&db)?;
     }
     Ok(db)
 }
 
+fn create_tables(db: &Database) -> eyre::Result<()> {
+    let write_txn = db.begin_write()?;
+    {
+        write_txn.open_table(PACKAGES)?;
+        write_txn.open_table(FILES)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+/// The database of a command that changes it. A dry run of the command only reads it, and reads
+/// a database that does not exist yet as an empty one.
+pub enum Db {
+    Writable(Database),
+    DryRun(Box<dyn ReadableDatabase>),
+}
+
+impl Db {
+    /// Opens the database like `open`, or without creating or writing anything for a dry run
+    pub fn open(path: &Path, dry_run: bool) -> eyre::Result<Self> {
+        if !dry_run {
+            return Ok(Db::Writable(open(path)?));
+        }
+        if fs::exists(path).context("Failed to open the database")? {
+            return Ok(Db::DryRun(Box::new(open_read_only(path)?)));
+        }
+        let db = Database::builder().create_with_backend(InMemoryBackend::new())?;
+        create_tables(&db)?;
+        Ok(Db::DryRun(Box::new(db)))
+    }
+
+    pub fn begin_write(&self) -> eyre::Result<WriteTransaction> {
+        match self {
+            Db::Writable(db) => Ok(db.begin_write()?),
+            Db::DryRun(_) => bail!("Cannot write to the database during a dry run"),
+        }
+    }
+}
+
+impl ReadableDatabase for Db {
+    fn begin_read(&self) -> Result<ReadTransaction, TransactionError> {
+        match self {
+            Db::Writable(db) => db.begin_read(),
+            Db::DryRun(db) => db.begin_read(),
+        }
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        match self {
+            Db::Writable(db) => db.cache_stats(),
+            Db::DryRun(db) => db.cache_stats(),
+        }
+    }
+}
+
+/// Opens the database for a command that only reads it, without write access to the file. A
+/// missing database is an error rather than an empty one.
+pub fn open_read_only(path: &Path) -> eyre::Result<ReadOnlyDatabase> {
+    if !fs::exists(path).context("Failed to open the database")? {
+        bail!("No package database at `{}`", path.display());
+    }
+    ReadOnlyDatabase::open(path)
+        .with_context(|| format!("Failed to open the database `{}`", path.display()))
+}
+
 pub const PACKAGES: TableDefinition<&str, &[u8]> = TableDefinition::new("PKGS");
 
-/// Should not store directories
+/// Files and symlinks shipped by packages, directories are in `DIRS`
 pub const FILES: TableDefinition<&str, &[u8]> = TableDefinition::new("FILES");
 
+/// Directories shipped by packages, created on first use
+pub const DIRS: TableDefinition<&str, &[u8]> = TableDefinition::new("DIRS");
+
+/// Files written next to a locally modified backup file instead of replacing it, mapped to the
+/// file they are meant to replace. Created on first use.
+pub const MERGES: TableDefinition<&str, &str> = TableDefinition::new("MERGES");
+
+/// Generation time of the newest index seen from each repository, created on first use
+pub const REPOS: TableDefinition<&str, u64> = TableDefinition::new("REPOS");
+
+/// `InstallReason` of each package, created on first use. Packages without a row were
+/// installed explicitly.
+pub const REASONS: TableDefinition<&str, u8> = TableDefinition::new("REASONS");
+
+/// Packages held with `meow-pkg hold`, created on first use. Other versions of a held package are
+/// not installed and it is not removed unless `--ignore-hold` is given.
+pub const HOLDS: TableDefinition<&str, ()> = TableDefinition::new("HOLDS");
+
+pub fn is_held<T>(holds_table: Option<&T>, name: &str) -> eyre::Result<bool>
+where T: ReadableTable<&'static str, ()> {
+    match holds_table {
+        Some(holds_table) => Ok(holds_table.get(name)?.is_some()),
+        None => Ok(false),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InstallReason {
+    /// Asked for by the user
+    Explicit,
+    /// Pulled in to satisfy the dependencies of another package
+    Dependency,
+}
+
+impl InstallReason {
+    pub fn get<T>(reasons_table: Option<&T>, name: &str) -> eyre::Result<Self>
+    where T: ReadableTable<&'static str, u8> {
+        let Some(reasons_table) = reasons_table else {
+            return Ok(InstallReason::Explicit);
+        };
+        Ok(match reasons_table.get(name)?.map(|row| row.value()) {
+            Some(1) => InstallReason::Dependency,
+            _ => InstallReason::Explicit,
+        })
+    }
+
+    pub fn set(self, reasons_table: &mut Table<&str, u8>, name: &str) -> eyre::Result<()> {
+        match self {
+            InstallReason::Explicit => reasons_table.remove(name)?,
+            InstallReason::Dependency => reasons_table.insert(name, 1)?,
+        };
+        Ok(())
+    }
+}
+
+/// Opens a table for reading, `None` if no write has created it yet
+pub fn open_optional_table<K, V>(
+    read_txn: &ReadTransaction,
+    table: TableDefinition<K, V>,
+) -> eyre::Result<Option<ReadOnlyTable<K, V>>>
+where
+    K: Key + 'static,
+    V: Value + 'static,
+{
+    match read_txn.open_table(table) {
+        Ok(table) => Ok(Some(table)),
+        Err(TableError::TableDoesNotExist(_)) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
 #[derive(bincode::Decode, bincode::Encode)]
 pub struct FileRecord {
     pub size: u64,
@@ -41,6 +188,21 @@ pub struct FileRecord {
     pub checksum: u64,
     /// Name of the package that owns this file or symlink
     pub package: String,
+    /// Set if a `NoUpgrade` or `NoExtract` glob or `--adopt` kept the package's version off the
+    /// disk
+    pub skipped: Option<Skipped>,
+}
+
+/// Why the file on disk is not the one the package ships
+#[derive(Clone, Copy, PartialEq, Eq, Debug, bincode::Decode, bincode::Encode)]
+pub enum Skipped {
+    /// The file that was already there was kept
+    NoUpgrade,
+    /// The file was never extracted
+    NoExtract,
+    /// The untracked file that was already there was kept with `--adopt`, the record holds its
+    /// checksum instead of the package's
+    Adopted,
 }
 
 impl From<&MeowZipEntry> for FileRecord {
@@ -52,6 +214,7 @@ impl From<&MeowZipEntry> for FileRecord {
             gid: value.gid,
             checksum: value.checksum,
             package: String::new(),
+            skipped: None,
         }
     }
 }
@@ -61,9 +224,36 @@ impl FileRecord {
         self.package = package;
         self
     }
+
+    pub fn with_skipped(mut self, skipped: Option<Skipped>) -> Self {
+        self.skipped = skipped;
+        self
+    }
 }
 
 impl From<&[u8]> for FileRecord {
+    fn from(value: &[u8]) -> Self {
+        let config = bincode::config::standard();
+        if let Ok((record, _)) = bincode::decode_from_slice(value, config) {
+            return record;
+        }
+        // Records written before `skipped` existed end after the package name
+        let ((size, mode, uid, gid, checksum, package), _) =
+            bincode::decode_from_slice(value, config).unwrap();
+        FileRecord { size, mode, uid, gid, checksum, package, skipped: None }
+    }
+}
+
+#[derive(bincode::Decode, bincode::Encode)]
+pub struct DirRecord {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Names of the packages that ship this directory, it is removed with the last one
+    pub owners: Vec<String>,
+}
+
+impl From<&[u8]> for DirRecord {
     fn from(value: &[u8]) -> Self {
         bincode::decode_from_slice(value, bincode::config::standard()).unwrap().0
     }
@@ -71,6 +261,95 @@ impl From<&[u8]> for FileRecord {
 
 impl From<&[u8]> for MeowZipMetadata {
     fn from(value: &[u8]) -> Self {
-        bincode::decode_from_slice(value, bincode::config::standard()).unwrap().0
+        let config = bincode::config::standard();
+        if let Ok((pkgmeta, _)) = bincode::decode_from_slice(value, config) {
+            return pkgmeta;
+        }
+        // Rows written by the first release end after the file list
+        let (
+            (
+                name,
+                version,
+                release,
+                depends,
+                packager,
+                license,
+                pre_install,
+                post_install,
+                pre_remove,
+                post_remove,
+                filelist,
+            ),
+            _,
+        ) = bincode::decode_from_slice(value, config).unwrap();
+        MeowZipMetadata {
+            name,
+            version,
+            release,
+            depends,
+            packager,
+            license,
+            pre_install,
+            post_install,
+            pre_remove,
+            post_remove,
+            filelist,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> MeowZipEntry {
+        MeowZipEntry {
+            filepath: "/usr/bin/a".into(),
+            size: 3,
+            mode: 0o100755,
+            uid: 0,
+            gid: 0,
+            checksum: 42,
+        }
+    }
+
+    #[test]
+    fn test_decode_first_release_row() {
+        // Packages installed by the first release were stored without any of the added fields
+        let row = bincode::encode_to_vec(
+            (
+                ("a", "1.0", 1u64, vec!["b"], "packager", "MIT"),
+                (b"pre".to_vec(), b"post".to_vec(), Vec::<u8>::new(), Vec::<u8>::new()),
+                vec![entry()],
+            ),
+            bincode::config::standard(),
+        )
+        .unwrap();
+        let pkgmeta = MeowZipMetadata::from(row.as_slice());
+        assert_eq!(pkgmeta.name, "a");
+        assert_eq!(pkgmeta.depends, ["b"]);
+        assert_eq!(pkgmeta.license, "MIT");
+        assert_eq!(pkgmeta.pre_install, b"pre");
+        assert_eq!(pkgmeta.post_install, b"post");
+        assert_eq!(pkgmeta.filelist[0].filepath, Path::new("/usr/bin/a"));
+        assert!(pkgmeta.description.is_empty());
+        assert!(pkgmeta.backup.is_empty());
+    }
+
+    #[test]
+    fn test_decode_current_row() {
+        let pkgmeta = MeowZipMetadata {
+            name: "a".to_owned(),
+            description: "An example".to_owned(),
+            backup: vec!["/etc/a.conf".to_owned()],
+            filelist: vec![entry()],
+            ..Default::default()
+        };
+        let row = bincode::encode_to_vec(&pkgmeta, bincode::config::standard()).unwrap();
+        let decoded = MeowZipMetadata::from(row.as_slice());
+        assert_eq!(decoded.description, "An example");
+        assert_eq!(decoded.backup, ["/etc/a.conf"]);
+        assert_eq!(decoded.filelist.len(), 1);
     }
 }
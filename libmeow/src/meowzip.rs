@@ -4,7 +4,7 @@ use std::path::{Path, PathBuf};
 use bincode::{Decode, Encode};
 use eyre::bail;
 
-#[derive(Encode, Decode)]
+#[derive(Clone, Encode, Decode, Default)]
 pub struct MeowZipMetadata {
     pub name: String,
     pub version: String,
@@ -17,9 +17,19 @@ pub struct MeowZipMetadata {
     pub pre_remove: Vec<u8>,
     pub post_remove: Vec<u8>,
     pub filelist: Vec<MeowZipEntry>,
+    // New fields go at the end, database rows written before they existed stop short of them
+    pub description: String,
+    /// Virtual packages this package stands in for, as `name` or `name=version`
+    pub provides: Vec<String>,
+    /// Packages that cannot be installed alongside this one
+    pub conflicts: Vec<String>,
+    /// Packages this one takes over from when they are installed
+    pub replaces: Vec<String>,
+    /// Globs of the configuration files that are kept when modified locally
+    pub backup: Vec<String>,
 }
 
-#[derive(Encode, Decode)]
+#[derive(Clone, Encode, Decode)]
 pub struct MeowZipEntry {
     pub filepath: PathBuf,
     pub size: u64,
@@ -103,7 +113,31 @@ where T: Read {
 
         filelist.push(MeowZipEntry { filepath, size, mode, uid, gid, checksum });
     }
-    file.read_exact(&mut buf_u32)?;
+    // Optional sections follow the file list, each one is a lowercase tag and a length. Readers
+    // skip sections they do not know about so that new ones can be added.
+    let mut description = String::new();
+    let mut provides = vec![];
+    let mut conflicts = vec![];
+    let mut replaces = vec![];
+    let mut backup = vec![];
+    loop {
+        file.read_exact(&mut buf_u32)?;
+        if !buf_u32.iter().all(u8::is_ascii_lowercase) {
+            break;
+        }
+        file.read_exact(&mut buf_u64)?;
+        let section_len = u64::from_be_bytes(buf_u64) as usize;
+        let mut section = vec![0u8; section_len];
+        file.read_exact(&mut section)?;
+        match &buf_u32 {
+            b"desc" => description = String::from_utf8(section)?,
+            b"prov" => provides = split_list(&String::from_utf8(section)?),
+            b"conf" => conflicts = split_list(&String::from_utf8(section)?),
+            b"repl" => replaces = split_list(&String::from_utf8(section)?),
+            b"bkup" => backup = split_list(&String::from_utf8(section)?),
+            _ => {}
+        }
+    }
     if &buf_u32 != b"ZSTD" {
         bail!(format!(
             "I don't know how to decompress the compression format {}",
@@ -114,13 +148,14 @@ where T: Read {
         name,
         version,
         release,
-        depends: depends
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect(),
+        depends: split_list(&depends),
         packager,
         license,
+        description,
+        provides,
+        conflicts,
+        replaces,
+        backup,
         pre_install,
         post_install,
         pre_remove,
@@ -129,6 +164,11 @@ where T: Read {
     })
 }
 
+/// Splits a comma separated list, ignoring empty entries
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
+}
+
 pub fn ensure_extension_is_mz(path: &Path) -> eyre::Result<()> {
     if path.extension().is_none_or(|ext| ext.to_str().unwrap() != "mz") {
         bail!("File extension must be `.mz`");
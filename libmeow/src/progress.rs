@@ -0,0 +1,45 @@
+use std::io::{IsTerminal, Write, stdout};
+
+use crate::{HEADER_STYLE, styled};
+
+/// Progress bar drawn on a single terminal line, it draws nothing if stdout is not a terminal
+pub struct Progress {
+    label: String,
+    total: u64,
+    done: u64,
+    percent: Option<u64>,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(label: String, total: u64) -> Self {
+        Progress { label, total, done: 0, percent: None, enabled: stdout().is_terminal() }
+    }
+
+    pub fn advance(&mut self, amount: u64) {
+        self.done = (self.done + amount).min(self.total);
+        let percent = (self.done * 100).checked_div(self.total).unwrap_or(100);
+        if !self.enabled || self.percent == Some(percent) {
+            return;
+        }
+        self.percent = Some(percent);
+        let cols = termion::terminal_size().map(|(cols, _rows)| cols).unwrap_or(80) as usize;
+        let width = cols.saturating_sub(self.label.chars().count() + 9).min(50);
+        let filled = width * percent as usize / 100;
+        print!(
+            "\r{} [{}{}] {:>3}%",
+            styled(&self.label, HEADER_STYLE),
+            "#".repeat(filled),
+            " ".repeat(width - filled),
+            percent
+        );
+        let _ = stdout().flush();
+    }
+
+    pub fn finish(&mut self) {
+        self.advance(self.total);
+        if self.enabled {
+            println!();
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bincode::{Decode, Encode};
+use eyre::{Context, bail};
+use minisign::PublicKey;
+use sha2::{Digest, Sha256};
+
+use crate::download::download;
+use crate::meowzip::{self, MeowZipMetadata};
+use crate::signature::{append_signature, read_appended_signature, verify_appended_signature};
+use crate::version;
+
+/// Name of the index file inside a repository directory
+pub const INDEX_FILENAME: &str = "meow.idx";
+
+/// Where files downloaded from repositories are kept unless the configuration file says otherwise
+pub const CACHE_DIR: &str = "/var/cache/meow";
+
+/// Marks the start of an index file, changed whenever the format changes
+const INDEX_MAGIC: &[u8] = b"MIDX";
+
+/// Index of the packages in a repository directory, stored in `INDEX_FILENAME` with a minisign
+/// signature appended the same way as meowzip files
+#[derive(Encode, Decode, Default)]
+pub struct RepoIndex {
+    /// When the index was written, in seconds since the Unix epoch. Clients refuse an index
+    /// older than the last one they saw so that an old index cannot be replayed.
+    pub generated: u64,
+    /// When the index expires, in seconds since the Unix epoch
+    pub valid_until: u64,
+    pub packages: Vec<RepoPackage>,
+}
+
+#[derive(Encode, Decode)]
+pub struct RepoPackage {
+    pub metadata: MeowZipMetadata,
+    /// File name of the meowzip file inside the repository directory
+    pub filename: String,
+    /// Size of the meowzip file in bytes
+    pub size: u64,
+    /// SHA-256 digest of the meowzip file
+    pub sha256: [u8; 32],
+}
+
+impl RepoPackage {
+    pub fn from_archive(path: &Path) -> eyre::Result<Self> {
+        let mut mz = BufReader::new(
+            File::open(path)
+                .with_context(|| format!("Failed to open meowzip file `{}`", path.display()))?,
+        );
+        let metadata = meowzip::read_metadata(&mut mz)?;
+        Ok(RepoPackage {
+            filename: path.file_name().unwrap().to_str().unwrap().to_owned(),
+            size: fs::metadata(path)?.len(),
+            sha256: archive_sha256(path)?,
+            metadata,
+        })
+    }
+
+    /// The file name this package is stored under in a repository
+    pub fn canonical_filename(metadata: &MeowZipMetadata) -> String {
+        format!("{}-{}-{}.mz", metadata.name, metadata.version, metadata.release)
+    }
+}
+
+impl RepoIndex {
+    /// Reads an index file without verifying its signature
+    pub fn read(path: &Path) -> eyre::Result<Self> {
+        let mut file = File::open(path)
+            .with_context(|| format!("Failed to open repository index `{}`", path.display()))?;
+        let (_, signed_len) = read_appended_signature(&mut file)?;
+        let mut buf = vec![];
+        file.take(signed_len).read_to_end(&mut buf)?;
+        let Some(body) = buf.strip_prefix(INDEX_MAGIC) else {
+            bail!(
+                "`{}` is not a valid repository index, recreate it with `meow-repo rebuild`",
+                path.display()
+            );
+        };
+        Ok(bincode::decode_from_slice(body, bincode::config::standard())?.0)
+    }
+
+    /// Stamps the index with the current time and signs it into a file, replacing the previous
+    /// one only once it is complete. The index expires after `valid_for`.
+    pub fn write(&mut self, path: &Path, valid_for: Duration) -> eyre::Result<()> {
+        let now = unix_time();
+        // Never go back in time, clients would take the index for a replayed one
+        self.generated = self.generated.max(now);
+        self.valid_until = now + valid_for.as_secs();
+        let tmppath = path.with_added_extension("tmp");
+        let mut file = File::create(&tmppath).context("Failed to create repository index")?;
+        file.write_all(INDEX_MAGIC)?;
+        file.write_all(&bincode::encode_to_vec(&*self, bincode::config::standard())?)?;
+        drop(file);
+        append_signature(&tmppath)?;
+        fs::rename(tmppath, path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RepoPackage> {
+        self.packages.iter().find(|package| package.metadata.name == name)
+    }
+
+    /// Adds a package to the index, replacing any other version of it. Returns the replaced
+    /// package, or an error if the index already has a newer version.
+    pub fn insert(&mut self, package: RepoPackage) -> eyre::Result<Option<RepoPackage>> {
+        let Some(i) =
+            self.packages.iter().position(|other| other.metadata.name == package.metadata.name)
+        else {
+            self.packages.push(package);
+            self.packages.sort_by(|a, b| a.metadata.name.cmp(&b.metadata.name));
+            return Ok(None);
+        };
+        let old = &self.packages[i].metadata;
+        if version::compare(
+            &old.version,
+            old.release,
+            &package.metadata.version,
+            package.metadata.release,
+        )
+        .is_gt()
+        {
+            bail!(
+                "The repository already has a newer version of `{}` ({}-{})",
+                old.name,
+                old.version,
+                old.release
+            );
+        }
+        Ok(Some(std::mem::replace(&mut self.packages[i], package)))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<RepoPackage> {
+        let i = self.packages.iter().position(|package| package.metadata.name == name)?;
+        Some(self.packages.remove(i))
+    }
+}
+
+/// A repository from the configuration file
+#[derive(Clone)]
+pub struct Repository {
+    pub name: String,
+    /// URLs of the repository directory, tried in order until one works. `file://` URLs are
+    /// paths on the host even when operating on another root, `http://` and `https://` ones are
+    /// downloaded into the cache directory.
+    pub servers: Vec<String>,
+    /// Public key the repository index must be signed with
+    pub publickey: Option<String>,
+}
+
+impl Repository {
+    pub fn new(name: String) -> Self {
+        Repository { name, servers: vec![], publickey: None }
+    }
+
+    /// Reads the repository index, verifying its signature if the repository has a public key.
+    /// Refuses an index that has expired or that is older than `last_generated`, the generation
+    /// time of the last index seen from this repository. `warn` is told about servers that
+    /// failed before another one was tried.
+    pub fn index(
+        &self,
+        cache_dir: &Path,
+        last_generated: u64,
+        warn: impl FnMut(&str),
+    ) -> eyre::Result<RepoIndex> {
+        let cachepath = cache_dir.join(format!("{}.idx", self.name));
+        self.try_servers(warn, |server| {
+            if let Some(dir) = server.strip_prefix("file://") {
+                return self.read_index(&Path::new(dir).join(INDEX_FILENAME), last_generated);
+            }
+            // The cached index is only replaced by one that passes the checks
+            let newpath = cachepath.with_added_extension("new");
+            // The index changes as a whole, a partial one is never worth resuming
+            let _ = fs::remove_file(newpath.with_added_extension("part"));
+            download(&server_url(server, INDEX_FILENAME), &newpath)?;
+            let index = self.read_index(&newpath, last_generated);
+            match index {
+                Ok(_) => fs::rename(&newpath, &cachepath)?,
+                Err(_) => fs::remove_file(&newpath)?,
+            }
+            index
+        })
+    }
+
+    /// Reads the repository index like `index` but without going to the network, from the
+    /// cache directory or from the first `file://` server if it was never downloaded
+    pub fn cached_index(&self, cache_dir: &Path, last_generated: u64) -> eyre::Result<RepoIndex> {
+        let cachepath = cache_dir.join(format!("{}.idx", self.name));
+        if fs::exists(&cachepath)? {
+            return self.read_index(&cachepath, last_generated);
+        }
+        match self.servers.iter().find_map(|server| server.strip_prefix("file://")) {
+            Some(dir) => self.read_index(&Path::new(dir).join(INDEX_FILENAME), last_generated),
+            None => bail!(
+                "Index of repository `{}` has not been downloaded yet, use `--refresh` to download it",
+                self.name
+            ),
+        }
+    }
+
+    /// Reads an index of this repository, checking its signature, expiry and generation
+    fn read_index(&self, path: &Path, last_generated: u64) -> eyre::Result<RepoIndex> {
+        if let Some(publickey) = &self.publickey {
+            let pk = PublicKey::from_base64(publickey)?;
+            let mut file = File::open(path)
+                .with_context(|| format!("Failed to open index of repository `{}`", self.name))?;
+            verify_appended_signature(&mut file, &pk).with_context(|| {
+                format!("Index of repository `{}` has an invalid signature", self.name)
+            })?;
+        }
+        let index = RepoIndex::read(path)?;
+        if index.valid_until < unix_time() {
+            bail!(
+                "Index of repository `{}` expired on {}",
+                self.name,
+                format_timestamp(index.valid_until)
+            );
+        }
+        if index.generated < last_generated {
+            bail!(
+                "Index of repository `{}` from {} is older than the one from {} seen before",
+                self.name,
+                format_timestamp(index.generated),
+                format_timestamp(last_generated)
+            );
+        }
+        Ok(index)
+    }
+
+    /// Returns the path to a package from this repository after checking it against the index,
+    /// packages from HTTP servers are downloaded into the package cache unless already there.
+    /// `warn` is told about servers that failed before another one was tried.
+    pub fn fetch(
+        &self,
+        package: &RepoPackage,
+        cache_dir: &Path,
+        warn: impl FnMut(&str),
+    ) -> eyre::Result<PathBuf> {
+        let cachepath = cache_dir.join("pkg").join(&package.filename);
+        if fs::exists(&cachepath)? && archive_sha256(&cachepath)? == package.sha256 {
+            return Ok(cachepath);
+        }
+        self.try_servers(warn, |server| {
+            let path = match server.strip_prefix("file://") {
+                Some(dir) => Path::new(dir).join(&package.filename),
+                None => {
+                    download(&server_url(server, &package.filename), &cachepath)?;
+                    cachepath.clone()
+                }
+            };
+            if archive_sha256(&path)? != package.sha256 {
+                if path == cachepath {
+                    fs::remove_file(&path)?;
+                }
+                bail!(
+                    "`{}` from repository `{}` does not match the checksum in the repository index",
+                    package.filename,
+                    self.name
+                );
+            }
+            Ok(path)
+        })
+    }
+
+    /// Calls `f` with each server until it succeeds, returns the error of the last server if
+    /// none of them do and passes the others to `warn`
+    fn try_servers<T, F>(&self, mut warn: impl FnMut(&str), mut f: F) -> eyre::Result<T>
+    where F: FnMut(&str) -> eyre::Result<T> {
+        let Some((last, mirrors)) = self.servers.split_last() else {
+            bail!("Repository `{}` has no servers", self.name);
+        };
+        for server in mirrors {
+            match f(server) {
+                Ok(value) => return Ok(value),
+                Err(error) => warn(&format!("{}: {:#}, trying the next server", server, error)),
+            }
+        }
+        f(last)
+    }
+}
+
+fn server_url(server: &str, filename: &str) -> String {
+    format!("{}/{}", server.trim_end_matches('/'), filename)
+}
+
+/// Finds the newest version of a package in the repositories, preferring the repository that
+/// comes first when they have the same version
+pub fn find_newest<'a>(
+    repos: &'a [(Repository, RepoIndex)],
+    name: &str,
+) -> Option<(&'a Repository, &'a RepoPackage)> {
+    let mut newest: Option<(&Repository, &RepoPackage)> = None;
+    for (repo, index) in repos {
+        let Some(package) = index.get(name) else {
+            continue;
+        };
+        if let Some((_, other)) = newest
+            && version::compare(
+                &package.metadata.version,
+                package.metadata.release,
+                &other.metadata.version,
+                other.metadata.release,
+            )
+            .is_le()
+        {
+            continue;
+        }
+        newest = Some((repo, package));
+    }
+    newest
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS UTC`
+pub fn format_timestamp(timestamp: u64) -> String {
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let days = timestamp / 86400;
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    let seconds = timestamp % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+pub fn archive_sha256(path: &Path) -> eyre::Result<[u8; 32]> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use minisign::{KeyPair, SecretKey};
+
+    use super::*;
+
+    /// A repository served from a fresh directory, with the key its index is signed with
+    fn repository(name: &str) -> (Repository, SecretKey, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("meow-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let KeyPair { pk, sk } = KeyPair::generate_unencrypted_keypair().unwrap();
+        let repo = Repository {
+            name: "core".to_owned(),
+            servers: vec![format!("file://{}", dir.display())],
+            publickey: Some(pk.to_base64()),
+        };
+        (repo, sk, dir)
+    }
+
+    fn write_index(dir: &Path, sk: &SecretKey, generated: u64, valid_until: u64) {
+        let index = RepoIndex { generated, valid_until, packages: vec![] };
+        let mut data = INDEX_MAGIC.to_vec();
+        data.extend(bincode::encode_to_vec(&index, bincode::config::standard()).unwrap());
+        let sig = minisign::sign(None, sk, data.as_slice(), None, None).unwrap().to_bytes();
+        data.extend(&sig);
+        data.extend((sig.len() as u64).to_be_bytes());
+        fs::write(dir.join(INDEX_FILENAME), data).unwrap();
+    }
+
+    #[test]
+    fn test_index_fresh() {
+        let (repo, sk, dir) = repository("index-fresh");
+        let now = unix_time();
+        write_index(&dir, &sk, now - 60, now + 3600);
+        assert_eq!(repo.index(&dir, now - 60, |_| {}).unwrap().generated, now - 60);
+        assert_eq!(repo.index(&dir, 0, |_| {}).unwrap().valid_until, now + 3600);
+    }
+
+    #[test]
+    fn test_index_expired() {
+        let (repo, sk, dir) = repository("index-expired");
+        let now = unix_time();
+        write_index(&dir, &sk, now - 7200, now - 3600);
+        let Err(error) = repo.index(&dir, 0, |_| {}) else { panic!("Expired index was accepted") };
+        assert_eq!(
+            error.to_string(),
+            format!("Index of repository `core` expired on {}", format_timestamp(now - 3600))
+        );
+    }
+
+    #[test]
+    fn test_index_replayed() {
+        let (repo, sk, dir) = repository("index-replayed");
+        let now = unix_time();
+        write_index(&dir, &sk, now - 60, now + 3600);
+        let Err(error) = repo.index(&dir, now, |_| {}) else {
+            panic!("Replayed index was accepted")
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Index of repository `core` from {} is older than the one from {} seen before",
+                format_timestamp(now - 60),
+                format_timestamp(now)
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951782400 + 3 * 3600 + 25 * 60), "2000-02-29 03:25:00 UTC");
+        assert_eq!(format_timestamp(1798761599), "2026-12-31 23:59:59 UTC");
+    }
+}
@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+
+use eyre::bail;
+
+use crate::meowzip::MeowZipMetadata;
+use crate::version::{self, Dependency};
+
+/// Works out which packages have to be installed, and in which order, for a request to be
+/// satisfied along with every dependency of the requested packages
+pub struct Resolver<'a> {
+    installed: HashMap<&'a str, &'a MeowZipMetadata>,
+    candidates: Vec<Candidate<'a>>,
+    /// Skip dependencies that cannot be satisfied instead of failing
+    pub breakdeps: bool,
+    /// Installed packages that must not be upgraded or replaced, with the reason why
+    pub frozen: HashMap<String, String>,
+}
+
+struct Candidate<'a> {
+    metadata: &'a MeowZipMetadata,
+    /// Always used for its name instead of the newest available version
+    pinned: bool,
+}
+
+#[derive(Default)]
+struct State<'a> {
+    chosen: HashMap<&'a str, usize>,
+    stack: Vec<usize>,
+    order: Vec<usize>,
+    cycles: Vec<Vec<String>>,
+}
+
+/// The outcome of a resolution
+#[derive(Debug)]
+pub struct Resolution {
+    /// Candidates to install, each one after the packages it depends on
+    pub order: Vec<usize>,
+    /// Installed packages that a candidate replaces, to be removed as it is installed
+    pub replaced: Vec<String>,
+    /// Dependency cycles that were broken, each starting and ending with the same package
+    pub cycles: Vec<Vec<String>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new<T>(installed: T) -> Self
+    where T: IntoIterator<Item = &'a MeowZipMetadata> {
+        Resolver {
+            installed: installed
+                .into_iter()
+                .map(|metadata| (metadata.name.as_str(), metadata))
+                .collect(),
+            candidates: vec![],
+            breakdeps: false,
+            frozen: HashMap::new(),
+        }
+    }
+
+    /// Makes a package available for installation, returns the index `resolve` refers to it by
+    pub fn add_candidate(&mut self, metadata: &'a MeowZipMetadata, pinned: bool) -> usize {
+        self.candidates.push(Candidate { metadata, pinned });
+        self.candidates.len() - 1
+    }
+
+    /// Works out the candidates to install for the requested packages, a name is satisfied by
+    /// a package of that name or by one that provides it
+    pub fn resolve(&self, requested: &[&str]) -> eyre::Result<Resolution> {
+        let mut state = State::default();
+        for name in requested {
+            if state.chosen.contains_key(name) {
+                continue;
+            }
+            let Some(i) = self.pick(&Dependency { name: name.to_string(), constraint: None })
+            else {
+                bail!("Package `{}` was not found in any repository", name);
+            };
+            self.visit(i, &mut state)?;
+        }
+        let replaced = self.replaced(&state);
+        if let Some((name, by)) = replaced.iter().find(|(name, _)| self.frozen.contains_key(**name))
+        {
+            bail!("`{}` would replace `{}` but `{}` is {}", by, name, name, self.frozen[*name]);
+        }
+        self.check_conflicts(&state, &replaced)?;
+        if !self.breakdeps {
+            self.check_installed(&state, &replaced)?;
+        }
+        Ok(Resolution {
+            order: state.order,
+            replaced: replaced.into_keys().map(str::to_owned).collect(),
+            cycles: state.cycles,
+        })
+    }
+
+    fn visit(&self, i: usize, state: &mut State<'a>) -> eyre::Result<()> {
+        if state.order.contains(&i) {
+            return Ok(());
+        }
+        if let Some(start) = state.stack.iter().position(|&j| j == i) {
+            let cycle = state.stack[start..]
+                .iter()
+                .chain([&i])
+                .map(|&j| self.candidates[j].metadata.name.clone())
+                .collect();
+            // Break the cycle here, the packages in it are installed in the order they were reached
+            state.cycles.push(cycle);
+            return Ok(());
+        }
+        let metadata = self.candidates[i].metadata;
+        state.chosen.insert(&metadata.name, i);
+        state.stack.push(i);
+        for depend in &metadata.depends {
+            let dependency = Dependency::parse(depend);
+            if let Some(&j) = state
+                .chosen
+                .values()
+                .find(|&&j| dependency.satisfied_by_package(self.candidates[j].metadata))
+            {
+                self.visit(j, state)?;
+                continue;
+            }
+            if let Some(&j) = state.chosen.get(dependency.name.as_str()) {
+                let chosen = self.candidates[j].metadata;
+                if !self.breakdeps {
+                    bail!(
+                        "`{}` requires `{}` but `{}` {}-{} is being installed",
+                        metadata.name,
+                        dependency,
+                        chosen.name,
+                        chosen.version,
+                        chosen.release
+                    );
+                }
+                continue;
+            }
+            if self.installed.values().any(|installed| {
+                !state.chosen.contains_key(installed.name.as_str())
+                    && dependency.satisfied_by_package(installed)
+            }) {
+                continue;
+            }
+            match self.pick(&dependency) {
+                Some(j) => {
+                    let picked = &self.candidates[j].metadata.name;
+                    match self.frozen.get(picked) {
+                        None => self.visit(j, state)?,
+                        Some(_) if self.breakdeps => {}
+                        Some(reason) => bail!(
+                            "`{}` requires `{}` but `{}` is {}",
+                            metadata.name,
+                            dependency,
+                            picked,
+                            reason
+                        ),
+                    }
+                }
+                None if self.breakdeps => {}
+                None => bail!(self.explain(metadata, &dependency)),
+            }
+        }
+        state.stack.pop();
+        state.order.push(i);
+        Ok(())
+    }
+
+    /// Picks the candidate for a dependency: the pinned or newest satisfying version of the
+    /// package it names, otherwise a package that provides it, preferring pinned candidates
+    /// and then the first provider in repository order
+    fn pick(&self, dependency: &Dependency) -> Option<usize> {
+        if self.candidates.iter().any(|candidate| candidate.metadata.name == dependency.name) {
+            return self.pick_version(&dependency.name, dependency);
+        }
+        let mut providers = self
+            .candidates
+            .iter()
+            .filter(|candidate| dependency.satisfied_by_package(candidate.metadata));
+        let provider = providers.clone().find(|candidate| candidate.pinned).or(providers.next())?;
+        self.pick_version(&provider.metadata.name, dependency)
+    }
+
+    /// Picks the pinned candidate named `name` if there is one, otherwise its newest version
+    /// that satisfies the dependency
+    fn pick_version(&self, name: &str, dependency: &Dependency) -> Option<usize> {
+        let candidates = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.metadata.name == name);
+        if let Some((i, candidate)) = candidates.clone().find(|(_, candidate)| candidate.pinned) {
+            return dependency.satisfied_by_package(candidate.metadata).then_some(i);
+        }
+        candidates
+            .filter(|(_, candidate)| dependency.satisfied_by_package(candidate.metadata))
+            .reduce(|newest, candidate| {
+                let ordering = version::compare(
+                    &candidate.1.metadata.version,
+                    candidate.1.metadata.release,
+                    &newest.1.metadata.version,
+                    newest.1.metadata.release,
+                );
+                if ordering.is_gt() { candidate } else { newest }
+            })
+            .map(|(i, _)| i)
+    }
+
+    fn explain(&self, metadata: &MeowZipMetadata, dependency: &Dependency) -> String {
+        let mut versions = vec![];
+        for other in self
+            .candidates
+            .iter()
+            .map(|candidate| candidate.metadata)
+            .chain(self.installed.get(dependency.name.as_str()).copied())
+            .filter(|other| other.name == dependency.name)
+        {
+            let version = format!("{}-{}", other.version, other.release);
+            if !versions.contains(&version) {
+                versions.push(version);
+            }
+        }
+        if versions.is_empty() {
+            return format!(
+                "`{}` requires `{}`, which is not installed or available in any repository",
+                metadata.name, dependency
+            );
+        }
+        format!(
+            "`{}` requires `{}` but only `{}` {} {} available",
+            metadata.name,
+            dependency,
+            dependency.name,
+            versions.join(", "),
+            if versions.len() == 1 { "is" } else { "are" }
+        )
+    }
+
+    /// Maps the installed packages named in the `replaces` of a chosen package to that package
+    fn replaced(&self, state: &State<'a>) -> HashMap<&'a str, &'a str> {
+        let mut replaced = HashMap::new();
+        for &i in &state.order {
+            let metadata = self.candidates[i].metadata;
+            for replace in &metadata.replaces {
+                let replace = Dependency::parse(replace);
+                if let Some(installed) = self.installed.get(replace.name.as_str())
+                    && !state.chosen.contains_key(replace.name.as_str())
+                    && replace.satisfied_by(&installed.version, installed.release)
+                {
+                    replaced.insert(installed.name.as_str(), metadata.name.as_str());
+                }
+            }
+        }
+        replaced
+    }
+
+    /// The installed packages that are neither upgraded nor replaced by a chosen package
+    fn kept(
+        &self,
+        state: &State<'a>,
+        replaced: &HashMap<&str, &str>,
+    ) -> impl Iterator<Item = &'a MeowZipMetadata> {
+        self.installed.values().copied().filter(|installed| {
+            !state.chosen.contains_key(installed.name.as_str())
+                && !replaced.contains_key(installed.name.as_str())
+        })
+    }
+
+    /// Makes sure no two chosen packages conflict with each other or with a package that
+    /// stays installed, packages replaced by a chosen one are removed so they cannot conflict
+    fn check_conflicts(
+        &self,
+        state: &State<'a>,
+        replaced: &HashMap<&str, &str>,
+    ) -> eyre::Result<()> {
+        for (n, &i) in state.order.iter().enumerate() {
+            let metadata = self.candidates[i].metadata;
+            for &j in &state.order[n + 1..] {
+                let other = self.candidates[j].metadata;
+                if conflicting(metadata, other) {
+                    bail!(
+                        "`{}` and `{}` conflict with each other and cannot be installed together",
+                        metadata.name,
+                        other.name
+                    );
+                }
+            }
+            if let Some(installed) =
+                self.kept(state, replaced).find(|installed| conflicting(metadata, installed))
+            {
+                bail!(
+                    "`{}` conflicts with the installed package `{}`, remove it first",
+                    metadata.name,
+                    installed.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes sure the packages that stay installed still have their dependencies satisfied
+    /// once the chosen packages replace the installed versions, and that no chosen package
+    /// relied on a package that is replaced
+    fn check_installed(
+        &self,
+        state: &State<'a>,
+        replaced: &HashMap<&str, &str>,
+    ) -> eyre::Result<()> {
+        let after: Vec<_> = state
+            .order
+            .iter()
+            .map(|&i| self.candidates[i].metadata)
+            .chain(self.kept(state, replaced))
+            .collect();
+        for metadata in &after {
+            for depend in &metadata.depends {
+                let dependency = Dependency::parse(depend);
+                if after.iter().any(|other| dependency.satisfied_by_package(other)) {
+                    continue;
+                }
+                let Some(before) =
+                    self.installed.values().find(|other| dependency.satisfied_by_package(other))
+                else {
+                    continue;
+                };
+                if let Some(&i) = state.chosen.get(before.name.as_str()) {
+                    let chosen = self.candidates[i].metadata;
+                    bail!(
+                        "`{}` requires `{}` but `{}` {}-{} would be installed",
+                        metadata.name,
+                        dependency,
+                        chosen.name,
+                        chosen.version,
+                        chosen.release
+                    );
+                }
+                bail!(
+                    "`{}` requires `{}` but `{}` would be replaced by `{}`",
+                    metadata.name,
+                    dependency,
+                    before.name,
+                    replaced[before.name.as_str()]
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether either package lists the other in its `conflicts`
+fn conflicting(metadata: &MeowZipMetadata, other: &MeowZipMetadata) -> bool {
+    let conflicts = |a: &MeowZipMetadata, b: &MeowZipMetadata| {
+        a.conflicts.iter().any(|conflict| Dependency::parse(conflict).satisfied_by_package(b))
+    };
+    metadata.name != other.name && (conflicts(metadata, other) || conflicts(other, metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, version: &str, depends: &[&str]) -> MeowZipMetadata {
+        MeowZipMetadata {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            release: 1,
+            depends: depends.iter().map(|depend| depend.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_order() {
+        let installed = [package("libc", "2.0", &[])];
+        let available = [
+            package("foo", "1.0", &["bar>=2", "libc"]),
+            package("bar", "1.4", &[]),
+            package("bar", "2.1", &["baz"]),
+            package("baz", "1.0", &["libc>=2"]),
+            package("libc", "2.1", &[]),
+        ];
+        let mut resolver = Resolver::new(&installed);
+        for metadata in &available {
+            resolver.add_candidate(metadata, false);
+        }
+        assert_eq!(resolver.resolve(&["foo"]).unwrap().order, [3, 2, 0]);
+    }
+
+    #[test]
+    fn test_resolve_unsatisfiable() {
+        let available = [package("foo", "1.0", &["bar>=2"]), package("bar", "1.4", &[])];
+        let mut resolver = Resolver::new([]);
+        for metadata in &available {
+            resolver.add_candidate(metadata, false);
+        }
+        assert_eq!(
+            resolver.resolve(&["foo"]).unwrap_err().to_string(),
+            "`foo` requires `bar>=2` but only `bar` 1.4-1 is available"
+        );
+        resolver.breakdeps = true;
+        assert_eq!(resolver.resolve(&["foo"]).unwrap().order, [0]);
+    }
+
+    #[test]
+    fn test_resolve_cycle() {
+        let available = [
+            package("foo", "1.0", &["bar", "libc"]),
+            package("bar", "1.0", &["foo"]),
+            package("libc", "1.0", &[]),
+        ];
+        let mut resolver = Resolver::new([]);
+        for metadata in &available {
+            resolver.add_candidate(metadata, false);
+        }
+        let resolution = resolver.resolve(&["foo"]).unwrap();
+        assert_eq!(resolution.order, [1, 2, 0]);
+        assert_eq!(resolution.cycles, [["foo", "bar", "foo"]]);
+        assert_eq!(resolver.resolve(&["bar", "foo"]).unwrap().order, [2, 0, 1]);
+    }
+
+    #[test]
+    fn test_resolve_frozen_dependency() {
+        let installed = [package("foo", "1.0", &["libc"]), package("libc", "1.0", &[])];
+        let available = [package("foo", "2.0", &["libc>=2"]), package("libc", "2.0", &[])];
+        let mut resolver = Resolver::new(&installed);
+        for metadata in &available {
+            resolver.add_candidate(metadata, false);
+        }
+        assert_eq!(resolver.resolve(&["foo"]).unwrap().order, [1, 0]);
+        resolver.frozen.insert("libc".to_owned(), "ignored with `--ignore`".to_owned());
+        assert_eq!(
+            resolver.resolve(&["foo"]).unwrap_err().to_string(),
+            "`foo` requires `libc>=2` but `libc` is ignored with `--ignore`"
+        );
+        resolver.breakdeps = true;
+        assert_eq!(resolver.resolve(&["foo"]).unwrap().order, [0]);
+    }
+
+    #[test]
+    fn test_resolve_breaks_installed() {
+        let installed = [package("foo", "1.0", &["bar<2"]), package("bar", "1.0", &[])];
+        let available = [package("bar", "2.0", &[])];
+        let mut resolver = Resolver::new(&installed);
+        resolver.add_candidate(&available[0], true);
+        assert_eq!(
+            resolver.resolve(&["bar"]).unwrap_err().to_string(),
+            "`foo` requires `bar<2` but `bar` 2.0-1 would be installed"
+        );
+    }
+
+    #[test]
+    fn test_resolve_provides() {
+        let mut installed = [package("foo", "1.0", &["sh"]), package("bash", "5.2", &[])];
+        installed[1].provides = vec!["sh".to_owned()];
+        let mut available = [
+            package("bar", "1.0", &["sh"]),
+            package("dash", "0.5", &[]),
+            package("bash", "5.2", &[]),
+        ];
+        available[1].provides = vec!["sh".to_owned()];
+        available[2].provides = vec!["sh".to_owned()];
+        let mut resolver = Resolver::new(&installed[..1]);
+        for metadata in &available {
+            resolver.add_candidate(metadata, false);
+        }
+        assert_eq!(resolver.resolve(&["bar"]).unwrap().order, [1, 0]);
+        assert_eq!(resolver.resolve(&["bash", "bar"]).unwrap().order, [2, 0]);
+        let mut resolver = Resolver::new(&installed);
+        for metadata in &available {
+            resolver.add_candidate(metadata, false);
+        }
+        assert_eq!(resolver.resolve(&["bar"]).unwrap().order, [0]);
+    }
+
+    #[test]
+    fn test_resolve_conflicts_and_replaces() {
+        let installed = [package("foo", "1.0", &[]), package("baz", "1.0", &["foo"])];
+        let mut available = [package("bar", "1.0", &[]), package("qux", "1.0", &[])];
+        available[0].conflicts = vec!["foo".to_owned()];
+        available[1].replaces = vec!["foo<2".to_owned()];
+        available[1].provides = vec!["foo=2.0".to_owned()];
+        let mut resolver = Resolver::new(&installed);
+        for metadata in &available {
+            resolver.add_candidate(metadata, false);
+        }
+        assert_eq!(
+            resolver.resolve(&["bar"]).unwrap_err().to_string(),
+            "`bar` conflicts with the installed package `foo`, remove it first"
+        );
+        let resolution = resolver.resolve(&["qux"]).unwrap();
+        assert_eq!(resolution.order, [1]);
+        assert_eq!(resolution.replaced, ["foo"]);
+        available[1].provides.clear();
+        let mut resolver = Resolver::new(&installed);
+        resolver.add_candidate(&available[1], false);
+        assert_eq!(
+            resolver.resolve(&["qux"]).unwrap_err().to_string(),
+            "`baz` requires `foo` but `foo` would be replaced by `qux`"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use eyre::{Context, bail};
+use minisign::{PublicKey, SecretKey, SignatureBox};
+
+fn open_minisign_secret_key() -> eyre::Result<SecretKey> {
+    let Some(home_dir) = dirs::home_dir() else {
+        bail!("Home directory not found");
+    };
+    let skpath = home_dir.join(".minisign/minisign.key");
+    if !fs::exists(&skpath)? {
+        bail!("minisign secret key does not exist. Generate it using `minisign -G`")
+    }
+    SecretKey::from_file(skpath, Some("".to_owned())).context("Failed to open minisign secret key")
+}
+
+/// Signs a file with the user's minisign secret key and appends the signature to it, followed
+/// by the length of the signature
+pub fn append_signature(path: &Path) -> eyre::Result<()> {
+    let sk = open_minisign_secret_key()?;
+    let mut file = File::open(path).context("Failed to open file to sign")?;
+    let sig = minisign::sign(None, &sk, &mut file, None, None)?.to_bytes();
+    let mut file =
+        OpenOptions::new().append(true).open(path).context("Failed to append signature")?;
+    file.write_all(&sig)?;
+    file.write_all(&(sig.len() as u64).to_be_bytes())?;
+    println!();
+    Ok(())
+}
+
+/// Reads the signature appended to a file, returns it along with the length of the signed data
+/// that precedes it
+pub fn read_appended_signature(file: &mut File) -> eyre::Result<(SignatureBox, u64)> {
+    let len = file.metadata()?.len();
+    if len < 8 {
+        bail!("File is too short to be signed");
+    }
+    let mut u64_buf = [0u8; 8];
+    file.seek(SeekFrom::End(-8))?;
+    file.read_exact(&mut u64_buf)?;
+    let signature_length = u64::from_be_bytes(u64_buf);
+    if signature_length > len - 8 {
+        bail!("File does not have a valid signature");
+    }
+    file.seek(SeekFrom::End(-8 - signature_length as i64))?;
+    let mut signature = vec![0; signature_length as usize];
+    file.read_exact(&mut signature)?;
+    let signature = String::from_utf8(signature)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok((SignatureBox::from_string(&signature)?, len - 8 - signature_length))
+}
+
+/// Verifies the signature appended to a file and returns the length of the signed data
+pub fn verify_appended_signature(file: &mut File, pk: &PublicKey) -> eyre::Result<u64> {
+    let (signature_box, signed_len) = read_appended_signature(file)?;
+    minisign::verify(pk, &signature_box, (&*file).take(signed_len), true, false, false)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(signed_len)
+}
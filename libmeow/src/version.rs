@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::meowzip::MeowZipMetadata;
+
+/// Compares two version strings segment by segment, runs of digits are compared as numbers
+/// and runs of letters alphabetically, a numeric segment is newer than an alphabetic one and
+/// when one version is a prefix of the other the longer one is newer.
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    let mut a = segments(a);
+    let mut b = segments(b);
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(x), Some(y)) => {
+                let x_numeric = x.starts_with(|c: char| c.is_ascii_digit());
+                let y_numeric = y.starts_with(|c: char| c.is_ascii_digit());
+                let ordering = match (x_numeric, y_numeric) {
+                    (true, true) => {
+                        let x = x.trim_start_matches('0');
+                        let y = y.trim_start_matches('0');
+                        x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+                    }
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) => x.cmp(y),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+/// Compares two packages by version and then by release number
+pub fn compare(version: &str, release: u64, other_version: &str, other_release: u64) -> Ordering {
+    vercmp(version, other_version).then(release.cmp(&other_release))
+}
+
+/// An entry of a package's `depends`, a package name optionally followed by one of the
+/// operators `<`, `<=`, `=`, `>=` or `>` and a version, which may end in `-<release>`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub constraint: Option<(Operator, String)>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operator {
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+}
+
+impl Dependency {
+    pub fn parse(depend: &str) -> Dependency {
+        let Some(i) = depend.find(['<', '=', '>']) else {
+            return Dependency { name: depend.trim().to_owned(), constraint: None };
+        };
+        let (name, rest) = depend.split_at(i);
+        let (operator, version) = [
+            ("<=", Operator::LessEqual),
+            (">=", Operator::GreaterEqual),
+            ("<", Operator::Less),
+            ("=", Operator::Equal),
+            (">", Operator::Greater),
+        ]
+        .into_iter()
+        .find_map(|(prefix, operator)| rest.strip_prefix(prefix).map(|version| (operator, version)))
+        .unwrap();
+        Dependency {
+            name: name.trim().to_owned(),
+            constraint: Some((operator, version.trim().to_owned())),
+        }
+    }
+
+    /// Whether a version of the package this dependency names satisfies its constraint
+    pub fn satisfied_by(&self, version: &str, release: u64) -> bool {
+        let Some((operator, wanted)) = &self.constraint else {
+            return true;
+        };
+        let ordering = if let Some((wanted_version, wanted_release)) = wanted.rsplit_once('-')
+            && let Ok(wanted_release) = wanted_release.parse()
+        {
+            compare(version, release, wanted_version, wanted_release)
+        } else {
+            vercmp(version, wanted)
+        };
+        match operator {
+            Operator::Less => ordering.is_lt(),
+            Operator::LessEqual => ordering.is_le(),
+            Operator::Equal => ordering.is_eq(),
+            Operator::GreaterEqual => ordering.is_ge(),
+            Operator::Greater => ordering.is_gt(),
+        }
+    }
+
+    /// Whether a package satisfies this dependency, either by its own name and version or
+    /// through one of its `provides`. A provided name without a version only satisfies
+    /// dependencies without a constraint.
+    pub fn satisfied_by_package(&self, metadata: &MeowZipMetadata) -> bool {
+        if metadata.name == self.name {
+            return self.satisfied_by(&metadata.version, metadata.release);
+        }
+        metadata.provides.iter().map(|provide| Dependency::parse(provide)).any(|provide| {
+            if provide.name != self.name {
+                return false;
+            }
+            let Some((_, version)) = &provide.constraint else {
+                return self.constraint.is_none();
+            };
+            if let Some((version, release)) = version.rsplit_once('-')
+                && let Ok(release) = release.parse()
+            {
+                self.satisfied_by(version, release)
+            } else {
+                self.satisfied_by(version, 0)
+            }
+        })
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some((operator, version)) = &self.constraint {
+            let operator = match operator {
+                Operator::Less => "<",
+                Operator::LessEqual => "<=",
+                Operator::Equal => "=",
+                Operator::GreaterEqual => ">=",
+                Operator::Greater => ">",
+            };
+            write!(f, "{}{}", operator, version)?;
+        }
+        Ok(())
+    }
+}
+
+fn segments(version: &str) -> impl Iterator<Item = &str> {
+    let mut rest = version;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| !c.is_ascii_alphanumeric());
+        let first = rest.chars().next()?;
+        let end = if first.is_ascii_digit() {
+            rest.find(|c: char| !c.is_ascii_digit())
+        } else {
+            rest.find(|c: char| !c.is_ascii_alphabetic())
+        }
+        .unwrap_or(rest.len());
+        let (segment, tail) = rest.split_at(end);
+        rest = tail;
+        Some(segment)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vercmp() {
+        assert_eq!(vercmp("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(vercmp("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(vercmp("1.0.1", "1.0"), Ordering::Greater);
+        assert_eq!(vercmp("1.001", "1.1"), Ordering::Equal);
+        assert_eq!(vercmp("2.0rc1", "2.0"), Ordering::Greater);
+        assert_eq!(vercmp("1.0a", "1.0b"), Ordering::Less);
+        assert_eq!(vercmp("1.0", "1.a"), Ordering::Greater);
+        assert_eq!(vercmp("20240101", "9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_dependency() {
+        let dependency = Dependency::parse("bar>=2");
+        assert_eq!(dependency.name, "bar");
+        assert_eq!(dependency.to_string(), "bar>=2");
+        assert!(!dependency.satisfied_by("1.4", 1));
+        assert!(dependency.satisfied_by("2.0", 1));
+        assert!(Dependency::parse("bar").satisfied_by("0.1", 1));
+        assert!(Dependency::parse("bar<1.0-3").satisfied_by("1.0", 2));
+        assert!(!Dependency::parse("bar=1.0").satisfied_by("1.1", 1));
+    }
+
+    #[test]
+    fn test_dependency_provides() {
+        let metadata = MeowZipMetadata {
+            name: "bash".to_owned(),
+            version: "5.2".to_owned(),
+            release: 1,
+            provides: vec!["sh".to_owned(), "bourne=5.2-1".to_owned()],
+            ..Default::default()
+        };
+        assert!(Dependency::parse("bash>=5").satisfied_by_package(&metadata));
+        assert!(Dependency::parse("sh").satisfied_by_package(&metadata));
+        assert!(!Dependency::parse("sh>=1").satisfied_by_package(&metadata));
+        assert!(Dependency::parse("bourne>=5").satisfied_by_package(&metadata));
+        assert!(!Dependency::parse("bourne>5.2-1").satisfied_by_package(&metadata));
+        assert!(!Dependency::parse("zsh").satisfied_by_package(&metadata));
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare("1.0", 2, "1.0", 1), Ordering::Greater);
+        assert_eq!(compare("1.0", 9, "1.1", 1), Ordering::Less);
+    }
+}
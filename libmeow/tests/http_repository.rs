@@ -0,0 +1,175 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fs, process, thread};
+
+use libmeow::download::download;
+use libmeow::meowzip::MeowZipMetadata;
+use libmeow::path_chroot;
+use libmeow::repo::{CACHE_DIR, RepoPackage, Repository};
+use sha2::{Digest, Sha256};
+
+/// Serves files over HTTP from a background thread and logs the requests it gets as
+/// `path` or `path from offset` for range requests
+fn serve(files: Vec<(&'static str, Vec<u8>)>) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(vec![]));
+    let log = Arc::clone(&requests);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let path = line.split_whitespace().nth(1).unwrap().to_owned();
+            let mut start = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(range) = line.to_lowercase().strip_prefix("range: bytes=") {
+                    start = Some(range.trim().trim_end_matches('-').parse::<usize>().unwrap());
+                }
+            }
+            log.lock().unwrap().push(match start {
+                Some(start) => format!("{} from {}", path, start),
+                None => path.clone(),
+            });
+            let mut response = vec![];
+            match (files.iter().find(|(name, _)| *name == path), start) {
+                (None, _) => {
+                    write!(response, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n").unwrap()
+                }
+                (Some((_, data)), None) => {
+                    write!(response, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", data.len())
+                        .unwrap();
+                }
+                (Some((_, data)), Some(start)) => write!(
+                    response,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                     Content-Range: bytes {}-{}/{}\r\n",
+                    data.len() - start,
+                    start,
+                    data.len() - 1,
+                    data.len()
+                )
+                .unwrap(),
+            }
+            write!(response, "Connection: close\r\n\r\n").unwrap();
+            if let Some((_, data)) = files.iter().find(|(name, _)| *name == path) {
+                response.extend_from_slice(&data[start.unwrap_or(0)..]);
+            }
+            stream.write_all(&response).unwrap();
+        }
+    });
+    (url, requests)
+}
+
+fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("meow-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+fn package(filename: &str, data: &[u8]) -> RepoPackage {
+    RepoPackage {
+        metadata: MeowZipMetadata {
+            name: "foo".to_owned(),
+            version: "1.0".to_owned(),
+            release: 1,
+            ..Default::default()
+        },
+        filename: filename.to_owned(),
+        size: data.len() as u64,
+        sha256: Sha256::digest(data).into(),
+    }
+}
+
+fn cache_dir(root: &Path) -> PathBuf {
+    path_chroot(CACHE_DIR, root)
+}
+
+fn cached(root: &Path, filename: &str) -> PathBuf {
+    cache_dir(root).join("pkg").join(filename)
+}
+
+#[test]
+fn test_download_resumes() {
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let (url, requests) = serve(vec![("/foo.mz", data.clone())]);
+    let root = temp_root("resume");
+    let dest = root.join("foo.mz");
+    fs::write(dest.with_added_extension("part"), &data[..40_000]).unwrap();
+
+    download(&format!("{}/foo.mz", url), &dest).unwrap();
+    assert_eq!(fs::read(&dest).unwrap(), data);
+    assert!(!fs::exists(dest.with_added_extension("part")).unwrap());
+    assert_eq!(*requests.lock().unwrap(), ["/foo.mz from 40000"]);
+}
+
+#[test]
+fn test_fetch_tries_mirrors() {
+    let data = b"meowzip".to_vec();
+    let (url, requests) = serve(vec![
+        ("/corrupt/foo-1.0-1.mz", b"meowzap".to_vec()),
+        ("/good/foo-1.0-1.mz", data.clone()),
+    ]);
+    let root = temp_root("mirrors");
+    let mut repo = Repository::new("core".to_owned());
+    repo.servers =
+        vec![format!("{}/missing", url), format!("{}/corrupt", url), format!("{}/good/", url)];
+    let package = package("foo-1.0-1.mz", &data);
+
+    let mut warnings = vec![];
+    let path = repo
+        .fetch(&package, &cache_dir(&root), |warning| warnings.push(warning.to_owned()))
+        .unwrap();
+    assert_eq!(path, cached(&root, "foo-1.0-1.mz"));
+    assert_eq!(fs::read(&path).unwrap(), data);
+    assert_eq!(
+        *requests.lock().unwrap(),
+        ["/missing/foo-1.0-1.mz", "/corrupt/foo-1.0-1.mz", "/good/foo-1.0-1.mz"]
+    );
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[1].starts_with(&format!("{}/corrupt: ", url)));
+
+    // A second fetch is served from the package cache
+    repo.fetch(&package, &cache_dir(&root), |_| {}).unwrap();
+    assert_eq!(requests.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_fetch_rejects_checksum_mismatch() {
+    let (url, _) = serve(vec![("/foo-1.0-1.mz", b"meowzap".to_vec())]);
+    let root = temp_root("mismatch");
+    let mut repo = Repository::new("core".to_owned());
+    repo.servers = vec![url];
+
+    let error =
+        repo.fetch(&package("foo-1.0-1.mz", b"meowzip"), &cache_dir(&root), |_| {}).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "`foo-1.0-1.mz` from repository `core` does not match the checksum in the repository index"
+    );
+    assert!(!fs::exists(cached(&root, "foo-1.0-1.mz")).unwrap());
+}
+
+#[test]
+fn test_index_keeps_cache_on_failure() {
+    let (url, _) = serve(vec![("/meow.idx", b"not an index".to_vec())]);
+    let root = temp_root("index-cache");
+    let mut repo = Repository::new("core".to_owned());
+    repo.servers = vec![url];
+    let cachepath = cache_dir(&root).join("core.idx");
+    fs::create_dir_all(cache_dir(&root)).unwrap();
+    fs::write(&cachepath, b"cached index").unwrap();
+
+    assert!(repo.index(&cache_dir(&root), 0, |_| {}).is_err());
+    assert_eq!(fs::read(&cachepath).unwrap(), b"cached index");
+    assert!(!fs::exists(cachepath.with_added_extension("new")).unwrap());
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use libmeow::config::Config;
+use libmeow::meowdb::{self, InstallReason};
+use libmeow::meowzip::MeowZipMetadata;
+use libmeow::version::Dependency;
+use libmeow::{columned, ensure_superuser, lock};
+use redb::{ReadableDatabase, ReadableTable};
+
+use crate::cli::RemoveOptions;
+use crate::remove::remove_locked;
+use crate::warn;
+
+pub fn autoremove(
+    dry_run: bool,
+    noconfirm: bool,
+    root: PathBuf,
+    config: &Config,
+) -> eyre::Result<()> {
+    if !dry_run {
+        ensure_superuser()?;
+    }
+    let _lock = if dry_run {
+        lock::shared(&config.db_path)?
+    } else {
+        lock::exclusive(&config.db_path, warn)?
+    };
+    let orphans = find_orphans(&meowdb::Db::open(&config.db_path, dry_run)?)?;
+    if orphans.is_empty() {
+        println!("No packages to remove");
+        return Ok(());
+    }
+    println!("The following packages were installed as dependencies and are no longer needed:");
+    columned::print(&orphans);
+    println!();
+    let options =
+        RemoveOptions { breakdeps: false, cascade: false, dry_run, noconfirm, ignore_hold: false };
+    remove_locked(&orphans, &options, &root, config)
+}
+
+/// Names of the packages installed as dependencies that no explicitly installed or held package
+/// needs, directly or through other packages
+fn find_orphans(db: &impl ReadableDatabase) -> eyre::Result<Vec<String>> {
+    let read_txn = db.begin_read()?;
+    let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
+    let reasons_table = meowdb::open_optional_table(&read_txn, meowdb::REASONS)?;
+    let holds_table = meowdb::open_optional_table(&read_txn, meowdb::HOLDS)?;
+    let mut installed = vec![];
+    for row in pkgs_table.iter()? {
+        installed.push(MeowZipMetadata::from(row?.1.value()));
+    }
+
+    let mut needed = vec![false; installed.len()];
+    let mut stack = vec![];
+    for (i, pkgmeta) in installed.iter().enumerate() {
+        if InstallReason::get(reasons_table.as_ref(), &pkgmeta.name)? == InstallReason::Explicit
+            || meowdb::is_held(holds_table.as_ref(), &pkgmeta.name)?
+        {
+            stack.push(i);
+        }
+    }
+    while let Some(i) = stack.pop() {
+        if needed[i] {
+            continue;
+        }
+        needed[i] = true;
+        for dependency in installed[i].depends.iter().map(|dep| Dependency::parse(dep)) {
+            // Every installed provider is kept, any of them may be the one in use
+            for (j, other) in installed.iter().enumerate() {
+                if dependency.satisfied_by_package(other) {
+                    stack.push(j);
+                }
+            }
+        }
+    }
+    Ok(installed
+        .into_iter()
+        .zip(needed)
+        .filter(|(_, needed)| !needed)
+        .map(|(pkgmeta, _)| pkgmeta.name)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use redb::Database;
+    use redb::backends::InMemoryBackend;
+
+    use super::*;
+
+    #[test]
+    fn test_find_orphans() {
+        // Name, dependencies, whether it was installed as a dependency and whether it is held
+        let packages = [
+            ("app", vec!["lib"], false, false),
+            ("lib", vec!["sh"], true, false),
+            ("bash", vec![], true, false),
+            ("old", vec!["olddep"], true, false),
+            ("olddep", vec![], true, false),
+            ("pinned", vec!["pinneddep"], true, true),
+            ("pinneddep", vec![], true, false),
+        ];
+        let db = Database::builder().create_with_backend(InMemoryBackend::new()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut pkgs_table = write_txn.open_table(meowdb::PACKAGES).unwrap();
+            let mut reasons_table = write_txn.open_table(meowdb::REASONS).unwrap();
+            let mut holds_table = write_txn.open_table(meowdb::HOLDS).unwrap();
+            for (name, depends, dependency, held) in packages {
+                let pkgmeta = MeowZipMetadata {
+                    name: name.to_owned(),
+                    depends: depends.into_iter().map(str::to_owned).collect(),
+                    provides: if name == "bash" { vec!["sh".to_owned()] } else { vec![] },
+                    ..Default::default()
+                };
+                let row = bincode::encode_to_vec(&pkgmeta, bincode::config::standard()).unwrap();
+                pkgs_table.insert(name, row.as_slice()).unwrap();
+                if dependency {
+                    InstallReason::Dependency.set(&mut reasons_table, name).unwrap();
+                }
+                if held {
+                    holds_table.insert(name, ()).unwrap();
+                }
+            }
+        }
+        write_txn.commit().unwrap();
+        assert_eq!(find_orphans(&db).unwrap(), ["old", "olddep"]);
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::bail;
+use file_mode::{FileType, Mode};
+use libmeow::config::Config;
+use libmeow::meowdb::{FileRecord, Skipped};
+use libmeow::meowzip::{MeowZipEntry, MeowZipMetadata};
+use libmeow::{columned, lock, meowdb, path_chroot};
+use redb::{ReadOnlyTable, ReadableDatabase, ReadableTable};
+
+use crate::install::is_backup;
+
+/// Compares the files of installed packages with what the packages shipped. Files kept off the
+/// disk by `NoExtract` are not missing and files kept by `NoUpgrade` or declared as backup files
+/// may be modified.
+pub fn check(packages: Vec<String>, root: PathBuf, config: &Config) -> eyre::Result<()> {
+    let _lock = lock::shared(&config.db_path)?;
+    let db = meowdb::open_read_only(&config.db_path)?;
+    let read_txn = db.begin_read()?;
+    let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
+    let files_table = read_txn.open_table(meowdb::FILES)?;
+
+    let mut pkgmetas = vec![];
+    if packages.is_empty() {
+        for row in pkgs_table.iter()? {
+            pkgmetas.push(MeowZipMetadata::from(row?.1.value()));
+        }
+    }
+    for name in &packages {
+        let Some(row) = pkgs_table.get(name.as_str())? else {
+            bail!("Package `{}` is not installed", name);
+        };
+        pkgmetas.push(MeowZipMetadata::from(row.value()));
+    }
+
+    let mut problems = vec![];
+    for pkgmeta in &pkgmetas {
+        for entry in &pkgmeta.filelist {
+            if let Some(problem) = check_path(pkgmeta, entry, config, &files_table, &root)? {
+                problems.push(vec![
+                    pkgmeta.name.clone(),
+                    entry.filepath.display().to_string(),
+                    problem.to_owned(),
+                ]);
+            }
+        }
+    }
+    if problems.is_empty() {
+        println!("No problems found in {} packages", pkgmetas.len());
+        return Ok(());
+    }
+    columned::print_table(&["Package", "Path", "Problem"], &problems);
+    bail!("{} problems found", problems.len());
+}
+
+/// Describes what is wrong with the path of an entry, `None` if nothing is
+fn check_path(
+    pkgmeta: &MeowZipMetadata,
+    entry: &MeowZipEntry,
+    config: &Config,
+    files_table: &ReadOnlyTable<&str, &[u8]>,
+    root: &Path,
+) -> eyre::Result<Option<&'static str>> {
+    let filetype = Mode::from(entry.mode).file_type().unwrap();
+    let record =
+        files_table.get(entry.filepath.to_str().unwrap())?.map(|row| FileRecord::from(row.value()));
+    // Another package took the file over
+    if record.as_ref().is_some_and(|record| record.package != pkgmeta.name) {
+        return Ok(None);
+    }
+    let skipped = record.as_ref().and_then(|record| record.skipped);
+    let matches =
+        |globs: &[glob::Pattern]| globs.iter().any(|pattern| pattern.matches_path(&entry.filepath));
+    let no_extract = skipped == Some(Skipped::NoExtract)
+        || !filetype.is_directory() && matches(&config.no_extract);
+    let dest = path_chroot(&entry.filepath, root);
+    let Ok(meta) = fs::symlink_metadata(&dest) else {
+        return Ok((!no_extract).then_some("missing"));
+    };
+    if no_extract {
+        return Ok(None);
+    }
+    let problem = match filetype {
+        FileType::Directory if meta.is_symlink() || !meta.is_dir() => "not a directory",
+        FileType::SymbolicLink if !meta.is_symlink() => "not a symlink",
+        FileType::RegularFile if !meta.is_file() => "not a regular file",
+        FileType::RegularFile => {
+            let may_differ = matches!(skipped, Some(Skipped::NoUpgrade | Skipped::Adopted))
+                || matches(&config.no_upgrade)
+                || is_backup(pkgmeta, &entry.filepath);
+            if may_differ || libmeow::file_checksum(&dest)? == entry.checksum {
+                return Ok(None);
+            }
+            "modified"
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(problem))
+}
@@ -1,11 +1,23 @@
+use std::io;
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use color_eyre::config::{HookBuilder, Theme};
+use libmeow::config::Config;
+use libmeow::meowdb::InstallReason;
 
+use crate::autoremove::autoremove;
+use crate::check::check;
+use crate::hold::hold;
 use crate::info::info;
 use crate::install::install;
 use crate::list::list;
+use crate::mark::mark;
+use crate::merge::merge;
+use crate::query::{rdeps, tree, why};
 use crate::remove::remove;
+use crate::search::search;
+use crate::upgrade::upgrade;
 
 #[derive(Parser)]
 #[command(about = format!("{}{}", libmeow::MEOW, "Package manager for meowOS"))]
@@ -16,26 +28,156 @@ struct Cli {
     /// The root directory (default: /)
     #[arg(long)]
     root: Option<PathBuf>,
+    /// The configuration file (default: /etc/meow/meow.conf inside the root directory)
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// The package database, which may be outside the root directory (default: `DBPath` from the
+    /// configuration file inside the root directory)
+    #[arg(long)]
+    dbpath: Option<PathBuf>,
+}
+
+/// Options shared by the commands that install packages
+#[derive(Args)]
+pub struct InstallOptions {
+    /// Break dependencies
+    #[arg(long)]
+    pub breakdeps: bool,
+    /// Show what would be done without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Do not ask for confirmation
+    #[arg(long)]
+    pub noconfirm: bool,
+    /// Overwrite files matching the glob that exist but no package owns, may be given more than
+    /// once
+    #[arg(long, value_name = "GLOB")]
+    pub overwrite_paths: Vec<String>,
+    /// Keep files that exist but no package owns and record them as locally modified
+    #[arg(long)]
+    pub adopt: bool,
+    /// Install other versions of held packages and let other packages replace them
+    #[arg(long)]
+    pub ignore_hold: bool,
+}
+
+/// Options of the commands that remove packages
+#[derive(Args)]
+pub struct RemoveOptions {
+    /// Break dependencies
+    #[arg(long)]
+    pub breakdeps: bool,
+    /// Also remove every package that depends on the packages, directly or not
+    #[arg(long, conflicts_with = "breakdeps")]
+    pub cascade: bool,
+    /// Show what would be done without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Do not ask for confirmation
+    #[arg(long)]
+    pub noconfirm: bool,
+    /// Remove held packages too
+    #[arg(long)]
+    pub ignore_hold: bool,
 }
 
 #[derive(Subcommand)]
 enum Command {
+    /// Install packages from the configured repositories or from meowzip files
     Install {
-        /// Path to meowzip package file to install
-        package: PathBuf,
+        /// Names of packages from the configured repositories or paths to meowzip files
+        #[arg(required = true)]
+        packages: Vec<String>,
         /// Force reinstall if package is already installed
         #[arg(long)]
         overwrite: bool,
-        /// Break dependencies
-        #[arg(long)]
-        breakdeps: bool,
+        #[command(flatten)]
+        options: InstallOptions,
     },
+    /// Uninstall packages
     Remove {
-        /// Name of package to uninstall
+        /// Names of packages to uninstall
+        #[arg(required = true)]
+        packages: Vec<String>,
+        #[command(flatten)]
+        options: RemoveOptions,
+    },
+    /// Upgrade every installed package that has a newer version in the repositories
+    Upgrade {
+        /// Do not upgrade this package, may be given more than once
+        #[arg(long, value_name = "PACKAGE")]
+        ignore: Vec<String>,
+        #[command(flatten)]
+        options: InstallOptions,
+    },
+    /// Remove packages installed as dependencies that no explicitly installed package needs
+    Autoremove {
+        /// Show what would be done without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Do not ask for confirmation
+        #[arg(long)]
+        noconfirm: bool,
+    },
+    /// Change whether installed packages count as explicitly installed or as dependencies
+    Mark {
+        /// Names of installed packages
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Mark as explicitly installed
+        #[arg(long, conflicts_with = "asdeps", required_unless_present = "asdeps")]
+        explicit: bool,
+        /// Mark as installed as a dependency, `autoremove` removes them once nothing needs them
+        #[arg(long)]
+        asdeps: bool,
+    },
+    /// Keep installed packages at their current version and refuse to remove them
+    Hold {
+        /// Names of installed packages
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Let held packages be upgraded and removed again
+    Unhold {
+        /// Names of held packages
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Search installed and repository packages by name and description
+    Search {
+        /// Case-insensitive regular expression to match
+        pattern: String,
+        /// Download the repository indexes instead of using the cached ones
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Print the dependency tree of an installed or repository package
+    Tree {
+        /// Name of the package
         package: String,
-        /// Break dependencies
+        /// Show dependencies only this many levels deep
+        #[arg(long, value_name = "LEVELS")]
+        depth: Option<usize>,
+        /// Download the repository indexes instead of using the cached ones
         #[arg(long)]
-        breakdeps: bool,
+        refresh: bool,
+    },
+    /// List the installed packages that depend on a package, directly or not
+    Rdeps {
+        /// Name of an installed package
+        package: String,
+    },
+    /// Show which explicitly installed packages need a package
+    Why {
+        /// Name of an installed package
+        package: String,
+    },
+    /// Review configuration files installed next to locally modified ones
+    Merge,
+    /// Report files of installed packages that are missing or were modified
+    Check {
+        /// Names of installed packages (default: every installed package)
+        packages: Vec<String>,
     },
     /// List installed packages
     List,
@@ -49,12 +191,34 @@ enum Command {
 pub fn run() -> eyre::Result<()> {
     let args = Cli::parse();
     let root = args.root.unwrap_or(PathBuf::from("/"));
+    // Errors in the configuration file are reported without colours, they are not known yet
+    let mut config = Config::load(&root, args.config.as_deref())?;
+    if let Some(dbpath) = args.dbpath {
+        config.db_path = dbpath;
+    }
+    libmeow::set_color(config.color);
+    let theme = if libmeow::use_color(io::stderr()) { Theme::dark() } else { Theme::new() };
+    HookBuilder::default().theme(theme).install()?;
     match args.command {
-        Command::Install { package, overwrite, breakdeps } => {
-            install(package, overwrite, breakdeps, root)
+        Command::Install { packages, overwrite, options } => {
+            install(packages, overwrite, options, root, &config)
+        }
+        Command::Remove { packages, options } => remove(packages, options, root, &config),
+        Command::Autoremove { dry_run, noconfirm } => autoremove(dry_run, noconfirm, root, &config),
+        Command::Mark { packages, explicit, asdeps: _ } => {
+            let reason = if explicit { InstallReason::Explicit } else { InstallReason::Dependency };
+            mark(packages, reason, &config)
         }
-        Command::Remove { package, breakdeps } => remove(package, breakdeps, root),
-        Command::List => list(root),
-        Command::Info { package } => info(root, package),
+        Command::Hold { packages } => hold(packages, true, &config),
+        Command::Unhold { packages } => hold(packages, false, &config),
+        Command::Upgrade { ignore, options } => upgrade(ignore, options, root, &config),
+        Command::Search { pattern, refresh } => search(pattern, refresh, &config),
+        Command::Tree { package, depth, refresh } => tree(package, depth, refresh, &config),
+        Command::Rdeps { package } => rdeps(package, &config),
+        Command::Why { package } => why(package, &config),
+        Command::Merge => merge(root, &config),
+        Command::Check { packages } => check(packages, root, &config),
+        Command::List => list(&config),
+        Command::Info { package } => info(package, &config),
     }
 }
@@ -0,0 +1,38 @@
+use eyre::bail;
+use libmeow::config::Config;
+use libmeow::meowzip::MeowZipMetadata;
+use libmeow::{ensure_superuser, lock, meowdb};
+use redb::ReadableTable;
+
+use crate::warn;
+
+/// Holds packages if `held` is set and releases them otherwise
+pub fn hold(packages: Vec<String>, held: bool, config: &Config) -> eyre::Result<()> {
+    ensure_superuser()?;
+    let _lock = lock::exclusive(&config.db_path, warn)?;
+    let db = meowdb::open(&config.db_path)?;
+    let write_txn = db.begin_write()?;
+    {
+        let pkgs_table = write_txn.open_table(meowdb::PACKAGES)?;
+        let mut holds_table = write_txn.open_table(meowdb::HOLDS)?;
+        let mut pkgmetas = vec![];
+        for name in &packages {
+            let Some(row) = pkgs_table.get(name.as_str())? else {
+                bail!("Package `{}` is not installed", name);
+            };
+            pkgmetas.push(MeowZipMetadata::from(row.value()));
+        }
+        for (name, pkgmeta) in packages.iter().zip(&pkgmetas) {
+            if held {
+                holds_table.insert(name.as_str(), ())?;
+                println!("Held `{}` at {}-{}", name, pkgmeta.version, pkgmeta.release);
+            } else if holds_table.remove(name.as_str())?.is_some() {
+                println!("Released `{}`", name);
+            } else {
+                println!("Package `{}` is not held", name);
+            }
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
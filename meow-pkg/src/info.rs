@@ -1,27 +1,42 @@
-use std::path::PathBuf;
-
 use eyre::bail;
 use humansize::format_size;
-use libmeow::meowdb;
+use libmeow::config::Config;
+use libmeow::meowdb::InstallReason;
 use libmeow::meowzip::MeowZipMetadata;
+use libmeow::{lock, meowdb};
 use redb::ReadableDatabase;
 
-pub fn info(root: PathBuf, package: String) -> eyre::Result<()> {
-    let db = meowdb::open(&root)?;
+pub fn info(package: String, config: &Config) -> eyre::Result<()> {
+    let _lock = lock::shared(&config.db_path)?;
+    let db = meowdb::open_read_only(&config.db_path)?;
     let read_txn = db.begin_read()?;
     let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
     let Some(row) = pkgs_table.get(&*package)? else {
         bail!("Package `{}` is not installed", package);
     };
     let metadata = MeowZipMetadata::from(row.value());
+    let reasons_table = meowdb::open_optional_table(&read_txn, meowdb::REASONS)?;
+    let reason = match InstallReason::get(reasons_table.as_ref(), &package)? {
+        InstallReason::Explicit => "Explicitly installed",
+        InstallReason::Dependency => "Installed as a dependency of another package",
+    };
+    let holds_table = meowdb::open_optional_table(&read_txn, meowdb::HOLDS)?;
+    let held = if meowdb::is_held(holds_table.as_ref(), &package)? { "Yes" } else { "No" };
     let total_size: u64 = metadata.filelist.iter().map(|entry| entry.size).sum();
     println!("Name:        {}", metadata.name);
+    println!("Description: {}", metadata.description);
     println!("Version:     {}", metadata.version);
     println!("Release:     {}", metadata.release);
     println!("Depends:     {}", metadata.depends.join(", "));
+    println!("Provides:    {}", metadata.provides.join(", "));
+    println!("Conflicts:   {}", metadata.conflicts.join(", "));
+    println!("Replaces:    {}", metadata.replaces.join(", "));
+    println!("Backup:      {}", metadata.backup.join(", "));
     println!("Packager:    {}", metadata.packager);
     println!("License:     {}", metadata.license);
+    println!("Reason:      {}", reason);
+    println!("Held:        {}", held);
     println!("Total Files: {}", metadata.filelist.len());
     println!("Total Size:  {}", format_size(total_size, humansize::DECIMAL));
-    todo!()
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, BufReader, Read};
 use std::os::linux::fs::MetadataExt;
@@ -8,73 +9,552 @@ use std::process::Command;
 use atomic_file_install::{atomic_install, atomic_symlink_file};
 use eyre::{Context, bail};
 use file_mode::{FileType, Mode};
-use libmeow::meowdb::FileRecord;
+use glob::Pattern;
+use humansize::{DECIMAL, format_size};
+use libmeow::config::Config;
+use libmeow::meowdb::{DirRecord, FileRecord, InstallReason, Skipped};
 use libmeow::meowzip::{self, MeowZipEntry, MeowZipMetadata, ensure_extension_is_mz};
-use libmeow::{columned, ensure_superuser, meowdb, path_chroot};
-use redb::{ReadOnlyTable, ReadableDatabase};
-
-use crate::remove::uninstall_path;
-
-pub fn install(path: PathBuf, overwrite: bool, breakdeps: bool, root: PathBuf) -> eyre::Result<()> {
-    ensure_superuser()?;
-    ensure_extension_is_mz(&path)?;
-    let mut mz = BufReader::new(File::open(&path).context("Failed to open package file")?);
-    let pkgmeta = meowzip::read_metadata(&mut mz)?;
-    let mut mz = zstd::Decoder::new(mz)?;
-    let db = meowdb::open(&root)?;
+use libmeow::progress::Progress;
+use libmeow::repo::{RepoIndex, RepoPackage, Repository};
+use libmeow::resolve::Resolver;
+use libmeow::version::Dependency;
+use libmeow::{columned, confirm, ensure_superuser, lock, meowdb, path_chroot};
+use nix::sys::statvfs::statvfs;
+use redb::{ReadOnlyTable, ReadableDatabase, ReadableTable, Table};
+
+use crate::cli::InstallOptions;
+use crate::remove::{other_owners, print_uninstall_plan, uninstall_path};
+use crate::warn;
+
+/// Packages at least this big show a progress bar while being extracted
+const PROGRESS_MIN_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Where each regular file is written before it is moved into place
+const STAGING_PATH: &str = "/tmp/meow-pkg-tempfile";
+
+pub fn install(
+    targets: Vec<String>,
+    overwrite: bool,
+    options: InstallOptions,
+    root: PathBuf,
+    config: &Config,
+) -> eyre::Result<()> {
+    if !options.dry_run {
+        ensure_superuser()?;
+    }
+    let _lock = if options.dry_run {
+        lock::shared(&config.db_path)?
+    } else {
+        lock::exclusive(&config.db_path, warn)?
+    };
+    install_locked(&targets, overwrite, None, &options, &root, config)
+}
+
+/// What `upgrade` already worked out before installing the new versions
+pub struct Upgrade {
+    /// The repositories the new versions were found in, not loaded a second time
+    pub repos: Vec<(Repository, RepoIndex)>,
+    /// Installed packages that are neither upgraded nor replaced, with the reason why
+    pub frozen: HashMap<String, String>,
+}
+
+/// Installs packages like `install`, for callers that already hold the lock. The targets are
+/// recorded as explicitly installed unless this is an upgrade, packages pulled in for their
+/// dependencies are recorded as installed as dependencies.
+pub fn install_locked(
+    targets: &[String],
+    overwrite: bool,
+    upgrade: Option<&Upgrade>,
+    options: &InstallOptions,
+    root: &Path,
+    config: &Config,
+) -> eyre::Result<()> {
+    let InstallOptions { breakdeps, dry_run, noconfirm, ignore_hold, .. } = *options;
+    let mut overwrite_paths = vec![];
+    for glob in &options.overwrite_paths {
+        overwrite_paths
+            .push(Pattern::new(glob).with_context(|| format!("Invalid glob `{}`", glob))?);
+    }
+    let db = meowdb::Db::open(&config.db_path, dry_run)?;
     let read_txn = db.begin_read()?;
     let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
     let files_table = read_txn.open_table(meowdb::FILES)?;
+    let dirs_table = meowdb::open_optional_table(&read_txn, meowdb::DIRS)?;
 
-    let mut missing = vec![];
-    for dependency in &pkgmeta.depends {
-        if pkgs_table.get(dependency.as_str())?.is_none() {
-            missing.push(dependency.as_str());
+    let (names, local) = read_targets(targets)?;
+    let mut installed = vec![];
+    for row in pkgs_table.iter()? {
+        installed.push(MeowZipMetadata::from(row?.1.value()));
+    }
+    let loaded;
+    let repos = match upgrade {
+        Some(upgrade) => upgrade.repos.as_slice(),
+        // Local files whose dependencies are all met need nothing from the repositories
+        None if names.len() == local.len() && !missing_dependencies(&local, &installed) => &[],
+        None => {
+            loaded = load_repositories(&db, config, !dry_run)?;
+            if !dry_run {
+                record_repositories(&db, &loaded)?;
+            }
+            if loaded.is_empty() && names.len() > local.len() {
+                bail!("No repositories are configured in `{}`", config.path.display());
+            }
+            loaded.as_slice()
         }
+    };
+    let frozen = upgrade.map(|upgrade| upgrade.frozen.clone()).unwrap_or_default();
+    let (sources, replaced) =
+        resolve_packages(&names, &local, &installed, repos, frozen, breakdeps)?;
+    let pkgmetas: Vec<_> = sources.iter().map(|source| source.metadata().clone()).collect();
+    let holds_table = meowdb::open_optional_table(&read_txn, meowdb::HOLDS)?;
+    let mut replacedmetas = vec![];
+    for name in &replaced {
+        if !ignore_hold && meowdb::is_held(holds_table.as_ref(), name)? {
+            bail!(
+                "Package `{}` is held, use `--ignore-hold` to let another package replace it",
+                name
+            );
+        }
+        let row = pkgs_table.get(name.as_str())?.unwrap();
+        replacedmetas.push(MeowZipMetadata::from(row.value()));
     }
-    if !missing.is_empty() && !breakdeps {
-        println!("The following dependencies are missing:");
-        columned::print(&missing);
-        bail!(
-            "Cannot install package due to missing dependencies, use `--breakdeps` to install anyway"
+
+    let mut oldpkgmetas = vec![];
+    for pkgmeta in &pkgmetas {
+        let oldpkgmeta =
+            pkgs_table.get(&*pkgmeta.name)?.map(|row| MeowZipMetadata::from(row.value()));
+        if oldpkgmeta.is_some() && !overwrite {
+            bail!(
+                "Package `{}` is already installed, use `--overwrite` to reinstall",
+                pkgmeta.name
+            );
+        }
+        if let Some(oldpkgmeta) = &oldpkgmeta
+            && (oldpkgmeta.version != pkgmeta.version || oldpkgmeta.release != pkgmeta.release)
+            && !ignore_hold
+            && meowdb::is_held(holds_table.as_ref(), &pkgmeta.name)?
+        {
+            bail!(
+                "Package `{}` is held at {}-{}, use `--ignore-hold` to install {}-{}",
+                pkgmeta.name,
+                oldpkgmeta.version,
+                oldpkgmeta.release,
+                pkgmeta.version,
+                pkgmeta.release
+            );
+        }
+        oldpkgmetas.push(oldpkgmeta);
+    }
+
+    // Paths owned by a package this transaction upgrades or replaces may move to another
+    // package, which the check for paths shipped twice covers
+    let changing: Vec<_> = pkgmetas
+        .iter()
+        .map(|pkgmeta| pkgmeta.name.as_str())
+        .chain(replaced.iter().map(String::as_str))
+        .collect();
+    let mut shipped: HashMap<&Path, (&str, &MeowZipEntry)> = HashMap::new();
+    let mut path_contexts = vec![];
+    for pkgmeta in &pkgmetas {
+        let mut pkg_path_contexts = vec![];
+        for entry in &pkgmeta.filelist {
+            let mut ctx = get_path_context(pkgmeta, entry, &files_table, root)?;
+            apply_path_policies(entry, &mut ctx, config, root)?;
+            check_conflicts(pkgmeta, entry, &ctx, &changing)?;
+            check_untracked(entry, &mut ctx, &overwrite_paths, options.adopt)?;
+            let is_dir = ctx.filetype.is_directory();
+            if is_dir && let Some(dirs_table) = &dirs_table {
+                check_dir_owners(pkgmeta, entry, dirs_table)?;
+            }
+            if let Some((other, other_entry)) =
+                shipped.insert(&entry.filepath, (&pkgmeta.name, entry))
+            {
+                if !(is_dir && Mode::from(other_entry.mode).file_type().unwrap().is_directory()) {
+                    bail!(
+                        "conflict: `{}` is shipped by both `{}` and `{}`",
+                        entry.filepath.display(),
+                        other,
+                        pkgmeta.name
+                    );
+                }
+                warn_dir_mismatch(entry, &pkgmeta.name, other_entry, other);
+            }
+            pkg_path_contexts.push(ctx);
+        }
+        path_contexts.push(pkg_path_contexts);
+    }
+
+    if dry_run {
+        let mut size_change = 0;
+        let mut added = 0;
+        let mut removed = 0;
+        for (i, pkgmeta) in pkgmetas.iter().enumerate() {
+            size_change += print_install_plan(
+                pkgmeta,
+                oldpkgmetas[i].as_ref(),
+                &path_contexts[i],
+                &shipped,
+                &files_table,
+                dirs_table.as_ref(),
+                root,
+            )?;
+            added += pkgmeta.filelist.iter().filter(|entry| !is_dir(entry)).count();
+            if let Some(oldpkgmeta) = &oldpkgmetas[i] {
+                removed += oldpkgmeta
+                    .filelist
+                    .iter()
+                    .filter(|entry| {
+                        !is_dir(entry) && !shipped.contains_key(entry.filepath.as_path())
+                    })
+                    .count();
+            }
+        }
+        for replacedmeta in &replacedmetas {
+            println!("Package `{}` is replaced and would be removed", replacedmeta.name);
+            for entry in replacedmeta.filelist.iter().rev() {
+                if shipped.contains_key(entry.filepath.as_path()) {
+                    continue;
+                }
+                let owners =
+                    other_owners(dirs_table.as_ref(), &entry.filepath, &[&replacedmeta.name])?;
+                size_change -= print_uninstall_plan(
+                    root,
+                    replacedmeta,
+                    &entry.filepath,
+                    &owners,
+                    &files_table,
+                )?;
+                if !is_dir(entry) {
+                    removed += 1;
+                }
+            }
+        }
+        println!(
+            "Database: {} package records written, {} file records written, {} file records removed",
+            pkgmetas.len(),
+            added,
+            removed
         );
+        println!("Disk usage change: {}", format_size_change(size_change));
+        return Ok(());
     }
 
-    let oldpkgmeta = pkgs_table.get(&*pkgmeta.name)?.map(|row| MeowZipMetadata::from(row.value()));
-    if oldpkgmeta.as_ref().is_some() && !overwrite {
-        bail!("Package '{}' is already installed, use `--overwrite` to reinstall", pkgmeta.name);
+    check_free_space(&pkgmetas, &path_contexts, root)?;
+
+    print_install_summary(&sources, &pkgmetas, &oldpkgmetas, &replacedmetas)?;
+    if !noconfirm && !confirm("Proceed with installation?")? {
+        bail!("Installation cancelled");
+    }
+    let mut paths = vec![];
+    for source in &sources {
+        paths.push(match source {
+            Source::Local((path, _)) => path.clone(),
+            Source::Repository(repo, package) => repo.fetch(package, &config.cache_dir, warn)?,
+        });
     }
 
-    let mut path_contexts = vec![];
-    for entry in &pkgmeta.filelist {
-        path_contexts.push(get_path_context(entry, &files_table, &root)?);
-        let ctx = path_contexts.last().unwrap();
-        check_conflicts(&pkgmeta, entry, ctx)?;
+    if root == "/" {
+        for replacedmeta in &replacedmetas {
+            run_hook(
+                &config.hook_shell,
+                &replacedmeta.name,
+                &replacedmeta.pre_remove,
+                "pre-remove",
+                &replacedmeta.version,
+                "",
+            )?;
+        }
+    }
+    for (i, pkgmeta) in pkgmetas.iter().enumerate() {
+        if root == "/" {
+            run_hook(
+                &config.hook_shell,
+                &pkgmeta.name,
+                &pkgmeta.pre_install,
+                "pre-install",
+                oldpkgmetas[i].as_ref().map(|m| m.version.as_str()).unwrap_or_default(),
+                &pkgmeta.version,
+            )?;
+        }
+        let mut mz = BufReader::new(File::open(&paths[i])?);
+        meowzip::read_metadata(&mut mz)?;
+        let mut mz = zstd::Decoder::new(mz)?;
+        extract(pkgmeta, &path_contexts[i], &mut mz, root)?;
     }
 
-    if &root == "/" {
-        run_hook(
-            &pkgmeta.name,
-            &pkgmeta.pre_install,
-            "pre-install",
-            oldpkgmeta.as_ref().map(|m| m.version.as_str()).unwrap_or_default(),
-            &pkgmeta.version,
-        )?;
+    let write_txn = db.begin_write()?;
+    {
+        let mut pkgs_table = write_txn.open_table(meowdb::PACKAGES)?;
+        let mut files_table = write_txn.open_table(meowdb::FILES)?;
+        let mut dirs_table = write_txn.open_table(meowdb::DIRS)?;
+        let mut reasons_table = write_txn.open_table(meowdb::REASONS)?;
+        let mut merges_table = write_txn.open_table(meowdb::MERGES)?;
+        let mut holds_table = write_txn.open_table(meowdb::HOLDS)?;
+
+        for (i, pkgmeta) in pkgmetas.iter().enumerate() {
+            if upgrade.is_none() && names.contains(&pkgmeta.name) {
+                InstallReason::Explicit.set(&mut reasons_table, &pkgmeta.name)?;
+            } else if oldpkgmetas[i].is_none() {
+                InstallReason::Dependency.set(&mut reasons_table, &pkgmeta.name)?;
+            }
+            for (entry, ctx) in pkgmeta.filelist.iter().zip(&path_contexts[i]) {
+                if Mode::from(entry.mode).file_type().unwrap().is_directory() {
+                    add_dir_owner(entry, &pkgmeta.name, &mut files_table, &mut dirs_table)?;
+                    continue;
+                }
+                if let FileAction::Meownew(meownew) = &ctx.action {
+                    merges_table
+                        .insert(meownew.to_str().unwrap(), entry.filepath.to_str().unwrap())?;
+                }
+                let record = file_record(pkgmeta, entry, ctx, root)?;
+                let row = bincode::encode_to_vec(&record, bincode::config::standard())?;
+                files_table.insert(&entry.filepath.to_str().unwrap(), &*row)?;
+            }
+
+            let metadata_bytes = bincode::encode_to_vec(pkgmeta, bincode::config::standard())?;
+            pkgs_table.insert(pkgmeta.name.as_str(), metadata_bytes.as_slice())?;
+        }
+
+        // Paths the old versions and the replaced packages ship are removed once every new record
+        // is in place, files that moved to another package were re-attributed above
+        for oldpkgmeta in oldpkgmetas.iter().flatten().chain(&replacedmetas) {
+            let newpkgmeta = pkgmetas.iter().find(|pkgmeta| pkgmeta.name == oldpkgmeta.name);
+            for entry in oldpkgmeta.filelist.iter().rev() {
+                if !still_shipped(entry, newpkgmeta, &shipped) {
+                    uninstall_path(root, entry, oldpkgmeta, &mut files_table, &mut dirs_table)?;
+                }
+            }
+        }
+
+        // A replacing package takes over the install reason
+        for replacedmeta in &replacedmetas {
+            pkgs_table.remove(replacedmeta.name.as_str())?;
+            holds_table.remove(replacedmeta.name.as_str())?;
+            let reason = InstallReason::get(Some(&reasons_table), &replacedmeta.name)?;
+            reasons_table.remove(replacedmeta.name.as_str())?;
+            if reason == InstallReason::Explicit
+                && let Some(pkgmeta) = pkgmetas.iter().find(|pkgmeta| {
+                    pkgmeta
+                        .replaces
+                        .iter()
+                        .any(|replace| Dependency::parse(replace).name == replacedmeta.name)
+                })
+            {
+                InstallReason::Explicit.set(&mut reasons_table, &pkgmeta.name)?;
+            }
+        }
     }
+    write_txn.commit()?;
 
-    for (entry, ctx) in pkgmeta.filelist.iter().zip(&path_contexts) {
-        if !ctx.filetype.is_directory() {
+    if root == "/" {
+        for replacedmeta in &replacedmetas {
+            run_hook(
+                &config.hook_shell,
+                &replacedmeta.name,
+                &replacedmeta.post_remove,
+                "post-remove",
+                &replacedmeta.version,
+                "",
+            )?;
+        }
+        for (i, pkgmeta) in pkgmetas.iter().enumerate() {
+            run_hook(
+                &config.hook_shell,
+                &pkgmeta.name,
+                &pkgmeta.post_install,
+                "post-install",
+                oldpkgmetas[i].as_ref().map(|m| m.version.as_str()).unwrap_or_default(),
+                &pkgmeta.version,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A meowzip file given on the command line and its metadata
+type LocalPackage = (PathBuf, MeowZipMetadata);
+
+/// Splits the targets into the names of the requested packages and the local meowzip files
+/// among them. Targets that end in `.mz` or contain a `/` are paths and everything else is the
+/// name of a package from the configured repositories.
+fn read_targets(targets: &[String]) -> eyre::Result<(Vec<String>, Vec<LocalPackage>)> {
+    let mut local = vec![];
+    let mut names = vec![];
+    for target in targets {
+        if !target.ends_with(".mz") && !target.contains('/') {
+            names.push(target.clone());
             continue;
+        }
+        let path = PathBuf::from(target);
+        ensure_extension_is_mz(&path)?;
+        let pkgmeta = read_package_metadata(&path)?;
+        if local.iter().any(|(_, other): &LocalPackage| other.name == pkgmeta.name) {
+            bail!("Package `{}` was given more than once", pkgmeta.name);
+        }
+        names.push(pkgmeta.name.clone());
+        local.push((path, pkgmeta));
+    }
+    Ok((names, local))
+}
+
+/// Where a package to install comes from
+enum Source<'a> {
+    Local(&'a LocalPackage),
+    /// Only fetched once the installation goes ahead, dry runs plan from the index alone
+    Repository(&'a Repository, &'a RepoPackage),
+}
+
+impl Source<'_> {
+    fn metadata(&self) -> &MeowZipMetadata {
+        match self {
+            Source::Local((_, pkgmeta)) => pkgmeta,
+            Source::Repository(_, package) => &package.metadata,
+        }
+    }
+
+    fn archive_size(&self) -> eyre::Result<u64> {
+        Ok(match self {
+            Source::Local((path, _)) => fs::metadata(path)?.len(),
+            Source::Repository(_, package) => package.size,
+        })
+    }
+}
+
+/// Works out the packages to install, each one after the packages it depends on, and the
+/// installed packages replaced by the new ones. Missing dependencies are pulled from the
+/// repositories.
+fn resolve_packages<'a>(
+    names: &[String],
+    local: &'a [LocalPackage],
+    installed: &[MeowZipMetadata],
+    repos: &'a [(Repository, RepoIndex)],
+    frozen: HashMap<String, String>,
+    breakdeps: bool,
+) -> eyre::Result<(Vec<Source<'a>>, Vec<String>)> {
+    let mut resolver = Resolver::new(installed);
+    resolver.breakdeps = breakdeps;
+    resolver.frozen = frozen;
+    for (_, pkgmeta) in local {
+        resolver.add_candidate(pkgmeta, true);
+    }
+    for (_, index) in repos {
+        for package in &index.packages {
+            resolver.add_candidate(&package.metadata, false);
+        }
+    }
+    let requested: Vec<_> = names.iter().map(String::as_str).collect();
+    let resolution = resolver.resolve(&requested)?;
+    for cycle in &resolution.cycles {
+        warn(&format!("dependency cycle: {}", cycle.join(" -> ")));
+    }
+    let available: Vec<_> = repos
+        .iter()
+        .flat_map(|(repo, index)| index.packages.iter().map(move |package| (repo, package)))
+        .collect();
+    let sources = resolution
+        .order
+        .into_iter()
+        .map(|i| match i.checked_sub(local.len()) {
+            None => Source::Local(&local[i]),
+            Some(i) => Source::Repository(available[i].0, available[i].1),
+        })
+        .collect();
+    Ok((sources, resolution.replaced))
+}
+
+/// Whether a local package depends on something that neither another local package nor an
+/// installed package it does not replace provides
+fn missing_dependencies(local: &[LocalPackage], installed: &[MeowZipMetadata]) -> bool {
+    let kept = installed
+        .iter()
+        .filter(|pkgmeta| !local.iter().any(|(_, other)| other.name == pkgmeta.name));
+    let available = local.iter().map(|(_, pkgmeta)| pkgmeta).chain(kept);
+    local.iter().flat_map(|(_, pkgmeta)| &pkgmeta.depends).any(|depend| {
+        let dependency = Dependency::parse(depend);
+        !available.clone().any(|pkgmeta| dependency.satisfied_by_package(pkgmeta))
+    })
+}
+
+fn read_package_metadata(path: &Path) -> eyre::Result<MeowZipMetadata> {
+    let mut mz = BufReader::new(
+        File::open(path)
+            .with_context(|| format!("Failed to open package file `{}`", path.display()))?,
+    );
+    meowzip::read_metadata(&mut mz)
+}
+
+/// Reads the index of every repository in the configuration file. An index older than the last
+/// one seen from its repository is refused. Unless `refresh` is set the cached indexes are used
+/// and repositories without a usable one are skipped with a warning.
+pub fn load_repositories(
+    db: &impl ReadableDatabase,
+    config: &Config,
+    refresh: bool,
+) -> eyre::Result<Vec<(Repository, RepoIndex)>> {
+    let read_txn = db.begin_read()?;
+    let repos_table = meowdb::open_optional_table(&read_txn, meowdb::REPOS)?;
+    let mut repos = vec![];
+    for repo in &config.repositories {
+        let last_generated = match &repos_table {
+            Some(table) => table.get(repo.name.as_str())?.map(|row| row.value()).unwrap_or(0),
+            None => 0,
         };
-        if let Some(oldmeta) = &ctx.oldmeta {
-            if oldmeta.is_symlink() || oldmeta.is_file() {
-                fs::remove_file(&entry.filepath)?;
-            }
-            if oldmeta.is_dir() && !oldmeta.is_symlink() {
-                continue;
+        let index = if refresh {
+            repo.index(&config.cache_dir, last_generated, warn)?
+        } else {
+            match repo.cached_index(&config.cache_dir, last_generated) {
+                Ok(index) => index,
+                Err(error) => {
+                    warn(&format!("{:#}", error));
+                    continue;
+                }
             }
+        };
+        repos.push((repo.clone(), index));
+    }
+    Ok(repos)
+}
+
+/// Records the generation time of the indexes as the newest seen from their repositories
+pub fn record_repositories(db: &meowdb::Db, repos: &[(Repository, RepoIndex)]) -> eyre::Result<()> {
+    if repos.is_empty() {
+        return Ok(());
+    }
+    let write_txn = db.begin_write()?;
+    {
+        let mut repos_table = write_txn.open_table(meowdb::REPOS)?;
+        for (repo, index) in repos {
+            repos_table.insert(repo.name.as_str(), index.generated)?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+fn extract<T>(
+    pkgmeta: &MeowZipMetadata,
+    path_contexts: &[PathContext],
+    mz: &mut T,
+    root: &Path,
+) -> eyre::Result<()>
+where
+    T: Read,
+{
+    let total_size = pkgmeta.filelist.iter().map(|entry| entry.size).sum();
+    let mut progress = (total_size >= PROGRESS_MIN_SIZE)
+        .then(|| Progress::new(format!("Extracting {}", pkgmeta.name), total_size));
+
+    for (entry, ctx) in pkgmeta.filelist.iter().zip(path_contexts) {
+        if !matches!(ctx.action, FileAction::CreateDir) {
+            continue;
+        };
+        let dest = path_chroot(&entry.filepath, root);
+        if let Some(oldmeta) = &ctx.oldmeta
+            && (oldmeta.is_symlink() || oldmeta.is_file())
+        {
+            fs::remove_file(&dest)?;
         }
-        let dest = path_chroot(&entry.filepath, &root);
         fs::create_dir_all(&dest)?;
         unix::fs::lchown(&dest, Some(entry.uid), Some(entry.gid))?;
         Mode::from(entry.mode).set_mode_path(dest)?;
@@ -84,79 +564,46 @@ pub fn install(path: PathBuf, overwrite: bool, breakdeps: bool, root: PathBuf) -
         if ctx.filetype.is_directory() {
             continue;
         }
-        let mut dest = path_chroot(&entry.filepath, &root);
+        let mut dest = path_chroot(&entry.filepath, root);
         let mut entrydata = mz.by_ref().take(entry.size);
-        match ctx.filetype {
-            FileType::SymbolicLink => {
-                if let Some(oldmeta) = ctx.oldmeta {
-                    if oldmeta.is_dir() && !oldmeta.is_symlink() {
-                        fs::remove_dir_all(&dest)?;
-                    }
-                }
+        if matches!(ctx.action, FileAction::Skip) {
+            io::copy(&mut entrydata, &mut io::sink())?;
+            if let Some(progress) = &mut progress {
+                progress.advance(entry.size);
+            }
+            continue;
+        }
+        if let Some(oldmeta) = &ctx.oldmeta
+            && oldmeta.is_dir()
+            && !oldmeta.is_symlink()
+            && matches!(ctx.action, FileAction::Replace)
+        {
+            fs::remove_dir_all(&dest)?;
+        }
+        match (&ctx.filetype, &ctx.action) {
+            (FileType::SymbolicLink, _) => {
                 let mut targetpath = String::new();
                 entrydata.read_to_string(&mut targetpath)?;
                 let targetpath = PathBuf::from(targetpath);
                 atomic_symlink_file(&targetpath, &dest)?;
             }
-            FileType::RegularFile => {
-                if let Some(oldmeta) = &ctx.oldmeta {
-                    if oldmeta.is_dir() && !oldmeta.is_symlink() {
-                        fs::remove_dir_all(&dest)?;
-                    }
-                }
-                let org = ctx.oldrecord.map(|oldrecord| oldrecord.checksum).unwrap_or(0);
-                let cur = if ctx.oldmeta.is_some() { libmeow::file_checksum(&dest)? } else { 0 };
-                let new = entry.checksum;
-                let mut discard = false;
-
-                // X-X-X
-                if org == cur && cur == new {
-                    discard = true;
-                }
-
-                // X-X-Y
-                if org == cur && cur != new {
-                    // Upgrade the old file.
-                    discard = false;
-                }
-
-                // X-Y-X
-                if org == new && cur != new {
-                    discard = true;
-                }
-
-                // X-Y-Y
-                if org != cur && cur == new {
-                    discard = true;
-                }
-
-                // X-Y-Z
-                if org != cur && cur != new && org != new {
-                    let basedest = dest;
-                    dest = basedest.with_added_extension("pacnew");
-                    let mut i = 2;
-                    while fs::exists(&dest)? {
-                        dest = basedest
-                            .with_added_extension("pacnew")
-                            .with_added_extension(i.to_string());
-                        i += 1;
-                    }
+            (FileType::RegularFile, FileAction::Keep { .. }) => {
+                io::copy(&mut entrydata, &mut io::sink())?;
+            }
+            (FileType::RegularFile, action) => {
+                if let FileAction::Meownew(meownew) = action {
+                    dest = path_chroot(meownew, root);
                     println!(
-                        "warning: `{}` installed as `{}`",
+                        "warning: `{}` installed as `{}`, run `meow-pkg merge` to resolve it",
                         &entry.filepath.display(),
-                        dest.display()
+                        meownew.display()
                     );
                 }
-
-                if discard {
-                    io::copy(&mut entrydata, &mut io::sink())?;
-                } else {
-                    // atomic_install will copy to parent of dest if not on same filesystem
-                    let tmpdest = PathBuf::from("/tmp/meow-pkg-tempfile");
-                    let mut newfile = File::create(&tmpdest)?;
-                    io::copy(&mut entrydata, &mut newfile)?;
-                    atomic_install(&tmpdest, &dest)?;
-                }
+                // atomic_install will copy to parent of dest if not on same filesystem
+                let tmpdest = PathBuf::from(STAGING_PATH);
+                let mut newfile = File::create(&tmpdest)?;
+                io::copy(&mut entrydata, &mut newfile)?;
+                atomic_install(&tmpdest, &dest)?;
             }
             _ => bail!("invalid file type in meowzip {}", entry.filepath.display()),
         }
@@ -164,47 +611,213 @@ pub fn install(path: PathBuf, overwrite: bool, breakdeps: bool, root: PathBuf) -
         if !ctx.filetype.is_symbolic_link() {
             Mode::from(entry.mode).set_mode_path_nofollow(&dest)?;
         }
+        if let Some(progress) = &mut progress {
+            progress.advance(entry.size);
+        }
+    }
+    if let Some(progress) = &mut progress {
+        progress.finish();
     }
+    Ok(())
+}
 
-    let write_txn = db.begin_write()?;
-    {
-        let mut pkgs_table = write_txn.open_table(meowdb::PACKAGES)?;
-        let mut files_table = write_txn.open_table(meowdb::FILES)?;
+fn print_install_summary(
+    sources: &[Source],
+    pkgmetas: &[MeowZipMetadata],
+    oldpkgmetas: &[Option<MeowZipMetadata>],
+    replacedmetas: &[MeowZipMetadata],
+) -> eyre::Result<()> {
+    let mut rows = vec![];
+    let mut total_size_change = 0;
+    let mut total_archive_size = 0;
+    for (i, pkgmeta) in pkgmetas.iter().enumerate() {
+        let size: u64 = pkgmeta.filelist.iter().map(|entry| entry.size).sum();
+        let oldsize: u64 = oldpkgmetas[i]
+            .as_ref()
+            .map(|oldpkgmeta| oldpkgmeta.filelist.iter().map(|entry| entry.size).sum())
+            .unwrap_or(0);
+        let size_change = size as i64 - oldsize as i64;
+        let archive_size = sources[i].archive_size()?;
+        total_size_change += size_change;
+        total_archive_size += archive_size;
+        rows.push(vec![
+            pkgmeta.name.clone(),
+            oldpkgmetas[i]
+                .as_ref()
+                .map(|oldpkgmeta| format!("{}-{}", oldpkgmeta.version, oldpkgmeta.release))
+                .unwrap_or_else(|| "-".to_owned()),
+            format!("{}-{}", pkgmeta.version, pkgmeta.release),
+            format_size_change(size_change),
+            format_size(archive_size, DECIMAL),
+        ]);
+    }
+    for replacedmeta in replacedmetas {
+        let size_change =
+            -(replacedmeta.filelist.iter().map(|entry| entry.size).sum::<u64>() as i64);
+        total_size_change += size_change;
+        rows.push(vec![
+            replacedmeta.name.clone(),
+            format!("{}-{}", replacedmeta.version, replacedmeta.release),
+            "replaced".to_owned(),
+            format_size_change(size_change),
+            "-".to_owned(),
+        ]);
+    }
+    columned::print_table(
+        &["Package", "Old Version", "New Version", "Size Change", "Archive Size"],
+        &rows,
+    );
+    println!();
+    println!("Total Size Change:  {}", format_size_change(total_size_change));
+    println!("Total Archive Size: {}", format_size(total_archive_size, DECIMAL));
+    println!();
+    Ok(())
+}
 
-        if let Some(old_pkgmeta) = &oldpkgmeta {
-            for entry in old_pkgmeta.filelist.iter().rev() {
-                if pkgmeta.filelist.iter().any(|e| e.filepath == entry.filepath) {
-                    continue;
+/// Prints what installing a package would do and returns the change in disk usage
+fn print_install_plan(
+    pkgmeta: &MeowZipMetadata,
+    oldpkgmeta: Option<&MeowZipMetadata>,
+    path_contexts: &[PathContext],
+    shipped: &HashMap<&Path, (&str, &MeowZipEntry)>,
+    files_table: &ReadOnlyTable<&str, &[u8]>,
+    dirs_table: Option<&ReadOnlyTable<&str, &[u8]>>,
+    root: &Path,
+) -> eyre::Result<i64> {
+    match oldpkgmeta {
+        Some(oldpkgmeta) => println!(
+            "Would upgrade `{}` {}-{} -> {}-{}",
+            pkgmeta.name, oldpkgmeta.version, oldpkgmeta.release, pkgmeta.version, pkgmeta.release
+        ),
+        None => {
+            println!("Would install `{}` {}-{}", pkgmeta.name, pkgmeta.version, pkgmeta.release)
+        }
+    }
+    let mut size_change = 0;
+    for (entry, ctx) in pkgmeta.filelist.iter().zip(path_contexts) {
+        let filepath = entry.filepath.display();
+        let oldsize = match &ctx.oldmeta {
+            Some(oldmeta) if !oldmeta.is_dir() => oldmeta.len() as i64,
+            _ => 0,
+        };
+        match &ctx.action {
+            FileAction::CreateDir => println!("  create   {}", filepath),
+            FileAction::Create => {
+                println!("  create   {}", filepath);
+                size_change += entry.size as i64;
+            }
+            FileAction::Replace => {
+                println!("  replace  {}", filepath);
+                size_change += entry.size as i64 - oldsize;
+            }
+            FileAction::Keep { modified: true } => {
+                println!("  keep     {} (locally modified)", filepath)
+            }
+            FileAction::Meownew(meownew) => {
+                if ctx.skipped == Some(Skipped::NoUpgrade) {
+                    println!("  keep     {} (NoUpgrade)", filepath);
+                } else {
+                    println!("  keep     {} (locally modified)", filepath);
                 }
-                uninstall_path(&root, &entry.filepath, &mut files_table)?;
+                println!("  create   {}", meownew.display());
+                size_change += entry.size as i64;
             }
+            FileAction::Skip => println!("  skip     {} (NoExtract)", filepath),
+            FileAction::KeepDir | FileAction::Keep { modified: false } => {}
         }
-
-        for entry in &pkgmeta.filelist {
-            let record = FileRecord::from(entry).with_package(pkgmeta.name.clone());
-            let row = bincode::encode_to_vec(&record, bincode::config::standard())?;
-            files_table.insert(&entry.filepath.to_str().unwrap(), &*row)?;
+    }
+    if let Some(oldpkgmeta) = oldpkgmeta {
+        for entry in oldpkgmeta.filelist.iter().rev() {
+            if shipped.contains_key(entry.filepath.as_path()) {
+                continue;
+            }
+            let owners = other_owners(dirs_table, &entry.filepath, &[&pkgmeta.name])?;
+            size_change -=
+                print_uninstall_plan(root, oldpkgmeta, &entry.filepath, &owners, files_table)?;
         }
-
-        let metadata_bytes = bincode::encode_to_vec(&pkgmeta, bincode::config::standard())?;
-        pkgs_table.insert(pkgmeta.name.as_str(), metadata_bytes.as_slice())?;
     }
-    write_txn.commit()?;
-
-    if &root == "/" {
-        run_hook(
-            &pkgmeta.name,
-            &pkgmeta.post_remove,
-            "post-install",
-            oldpkgmeta.as_ref().map(|m| m.version.as_str()).unwrap_or_default(),
-            &pkgmeta.version,
-        )?;
+    if root == "/" {
+        for (hook, hook_name) in
+            [(&pkgmeta.pre_install, "pre-install"), (&pkgmeta.post_install, "post-install")]
+        {
+            if !hook.is_empty() {
+                println!("  run      {} hook", hook_name);
+            }
+        }
     }
+    Ok(size_change)
+}
 
+/// Makes sure that every filesystem the packages will be extracted to has enough room for
+/// the files that are created or grow, and the staging filesystem for the largest of them.
+fn check_free_space(
+    pkgmetas: &[MeowZipMetadata],
+    path_contexts: &[Vec<PathContext>],
+    root: &Path,
+) -> eyre::Result<()> {
+    let mut needed: HashMap<u64, (PathBuf, i64)> = HashMap::new();
+    let mut largest = 0;
+    for (pkgmeta, path_contexts) in pkgmetas.iter().zip(path_contexts) {
+        for (entry, ctx) in pkgmeta.filelist.iter().zip(path_contexts) {
+            if ctx.filetype.is_regular_file()
+                && matches!(
+                    ctx.action,
+                    FileAction::Create | FileAction::Replace | FileAction::Meownew(_)
+                )
+            {
+                largest = largest.max(entry.size as i64);
+            }
+            let size = match &ctx.action {
+                FileAction::Create | FileAction::Meownew(_) => entry.size as i64,
+                FileAction::Replace => match &ctx.oldmeta {
+                    Some(oldmeta) if !oldmeta.is_dir() => entry.size as i64 - oldmeta.len() as i64,
+                    _ => entry.size as i64,
+                },
+                _ => continue,
+            };
+            let dest = path_chroot(&entry.filepath, root);
+            let Some(dir) = dest.ancestors().skip(1).find(|dir| dir.is_dir()) else {
+                continue;
+            };
+            let dev = fs::metadata(dir)?.st_dev();
+            needed.entry(dev).or_insert_with(|| (dir.to_owned(), 0)).1 += size;
+        }
+    }
+    // Regular files are staged one at a time, so the largest one has to fit there as well
+    let staging = Path::new(STAGING_PATH).parent().unwrap();
+    let dev = fs::metadata(staging)?.st_dev();
+    needed.entry(dev).or_insert_with(|| (staging.to_owned(), 0)).1 += largest;
+    for (dev, (dir, size)) in needed {
+        if size <= 0 {
+            continue;
+        }
+        let stat = statvfs(&dir)?;
+        let available = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+        if size as u64 > available {
+            let mut mount_point = dir.as_path();
+            while let Some(parent) = mount_point.parent()
+                && fs::metadata(parent)?.st_dev() == dev
+            {
+                mount_point = parent;
+            }
+            bail!(
+                "Not enough free space on `{}`, {} are needed but only {} are available",
+                mount_point.display(),
+                format_size(size as u64, DECIMAL),
+                format_size(available, DECIMAL)
+            );
+        }
+    }
     Ok(())
 }
 
+pub fn format_size_change(size_change: i64) -> String {
+    let sign = if size_change < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_size(size_change.unsigned_abs(), DECIMAL))
+}
+
 pub fn run_hook(
+    shell: &Path,
     package_name: &str,
     hook: &[u8],
     hook_name: &str,
@@ -212,7 +825,7 @@ pub fn run_hook(
     arg1: &str,
 ) -> eyre::Result<()> {
     let hook = str::from_utf8(hook).unwrap();
-    Command::new("/usr/bin/bash")
+    Command::new(shell)
         .args(["-c", hook, arg0, arg1])
         .status()?
         .exit_ok()
@@ -224,30 +837,270 @@ struct PathContext {
     filetype: FileType,
     oldrecord: Option<FileRecord>,
     oldmeta: Option<fs::Metadata>,
+    action: FileAction,
+    /// Set if a `NoUpgrade` or `NoExtract` glob or `--adopt` changed the action
+    skipped: Option<Skipped>,
+}
+
+/// What installing an entry does to the path it is installed to
+enum FileAction {
+    /// Create a directory, replacing a file or symlink at the path if there is one
+    CreateDir,
+    /// The directory already exists
+    KeepDir,
+    /// Nothing exists at the path yet
+    Create,
+    /// Overwrite whatever exists at the path
+    Replace,
+    /// Leave the existing file alone, because it either already matches the entry or only
+    /// differs from the previously installed version by local modifications
+    Keep { modified: bool },
+    /// Both the package and the existing file changed, so the entry is written to this path
+    /// next to the existing file instead
+    Meownew(PathBuf),
+    /// Leave the path alone because a `NoExtract` glob matches it
+    Skip,
+}
+
+/// Whether an entry is a directory, which is recorded in `DIRS` rather than `FILES`
+pub fn is_dir(entry: &MeowZipEntry) -> bool {
+    Mode::from(entry.mode).file_type().unwrap().is_directory()
+}
+
+/// Whether a path is one of the configuration files a package declares in its `backup`
+pub fn is_backup(pkgmeta: &MeowZipMetadata, path: &Path) -> bool {
+    pkgmeta.backup.iter().any(|glob| Pattern::new(glob).is_ok_and(|glob| glob.matches_path(path)))
+}
+
+/// Returns `filepath` with `extension` added, followed by a number if that path is taken
+pub fn free_path(filepath: &Path, extension: &str, root: &Path) -> eyre::Result<PathBuf> {
+    let mut path = filepath.with_added_extension(extension);
+    let mut i = 2;
+    while fs::exists(path_chroot(&path, root))? {
+        path = filepath.with_added_extension(extension).with_added_extension(i.to_string());
+        i += 1;
+    }
+    Ok(path)
 }
 
 fn get_path_context(
+    pkgmeta: &MeowZipMetadata,
     entry: &MeowZipEntry,
     files_table: &ReadOnlyTable<&str, &[u8]>,
     root: &Path,
 ) -> eyre::Result<PathContext> {
     let dest = path_chroot(&entry.filepath, root);
-    Ok(PathContext {
-        filetype: Mode::from(entry.mode).file_type().unwrap(),
-        oldrecord: files_table
-            .get(entry.filepath.to_str().unwrap())?
-            .map(|row| FileRecord::from(row.value())),
-        oldmeta: if fs::exists(&dest)? { Some(fs::symlink_metadata(dest)?) } else { None },
-    })
+    let filetype = Mode::from(entry.mode).file_type().unwrap();
+    let oldrecord =
+        files_table.get(entry.filepath.to_str().unwrap())?.map(|row| FileRecord::from(row.value()));
+    let oldmeta = if fs::exists(&dest)? { Some(fs::symlink_metadata(&dest)?) } else { None };
+    let mut skipped = None;
+    let action = match &oldmeta {
+        None if filetype.is_directory() => FileAction::CreateDir,
+        None => FileAction::Create,
+        Some(oldmeta) if filetype.is_directory() => {
+            if oldmeta.is_dir() && !oldmeta.is_symlink() {
+                FileAction::KeepDir
+            } else {
+                FileAction::CreateDir
+            }
+        }
+        Some(oldmeta) if filetype.is_regular_file() && oldmeta.is_file() => {
+            let org = oldrecord.as_ref().map(|oldrecord| oldrecord.checksum).unwrap_or(0);
+            let cur = libmeow::file_checksum(&dest)?;
+            let new = entry.checksum;
+            // An adopted file was never the package's, so it is kept like a modified backup file
+            let adopted = oldrecord
+                .as_ref()
+                .is_some_and(|oldrecord| oldrecord.skipped == Some(Skipped::Adopted));
+            if adopted && cur != new {
+                skipped = Some(Skipped::Adopted);
+            }
+            if cur == new {
+                // X-X-X or X-Y-Y
+                FileAction::Keep { modified: false }
+            } else if !adopted && (org == cur || !is_backup(pkgmeta, &entry.filepath)) {
+                // X-X-Y, local changes are only kept for backup files
+                FileAction::Replace
+            } else if org == new {
+                // X-Y-X
+                FileAction::Keep { modified: true }
+            } else {
+                // X-Y-Z
+                FileAction::Meownew(free_path(&entry.filepath, "meownew", root)?)
+            }
+        }
+        Some(_) => FileAction::Replace,
+    };
+    Ok(PathContext { filetype, oldrecord, oldmeta, action, skipped })
+}
+
+/// Whether a path an old version ships stays installed, because the new version of the package
+/// ships it again or because another package takes the file or symlink over. Directories other
+/// packages ship are left to `uninstall_path`, which drops the old version from their owners.
+fn still_shipped(
+    entry: &MeowZipEntry,
+    newpkgmeta: Option<&MeowZipMetadata>,
+    shipped: &HashMap<&Path, (&str, &MeowZipEntry)>,
+) -> bool {
+    let shipped_again =
+        newpkgmeta.is_some_and(|p| p.filelist.iter().any(|e| e.filepath == entry.filepath));
+    let moved = shipped.contains_key(entry.filepath.as_path()) && !is_dir(entry);
+    shipped_again || moved
+}
+
+/// The record of an installed file or symlink
+fn file_record(
+    pkgmeta: &MeowZipMetadata,
+    entry: &MeowZipEntry,
+    ctx: &PathContext,
+    root: &Path,
+) -> eyre::Result<FileRecord> {
+    let mut record =
+        FileRecord::from(entry).with_package(pkgmeta.name.clone()).with_skipped(ctx.skipped);
+    if ctx.skipped == Some(Skipped::Adopted) {
+        record.checksum = libmeow::file_checksum(&path_chroot(&entry.filepath, root))?;
+    }
+    Ok(record)
+}
+
+/// Applies the `NoExtract` and `NoUpgrade` globs of the configuration file to an entry.
+/// Directories are always created.
+fn apply_path_policies(
+    entry: &MeowZipEntry,
+    ctx: &mut PathContext,
+    config: &Config,
+    root: &Path,
+) -> eyre::Result<()> {
+    if ctx.filetype.is_directory() {
+        return Ok(());
+    }
+    if config.no_extract.iter().any(|pattern| pattern.matches_path(&entry.filepath)) {
+        ctx.action = FileAction::Skip;
+        ctx.skipped = Some(Skipped::NoExtract);
+    } else if ctx.filetype.is_regular_file()
+        && ctx.oldmeta.as_ref().is_some_and(|oldmeta| oldmeta.is_file())
+        && matches!(ctx.action, FileAction::Replace | FileAction::Meownew(_))
+        && config.no_upgrade.iter().any(|pattern| pattern.matches_path(&entry.filepath))
+    {
+        if matches!(ctx.action, FileAction::Replace) {
+            ctx.action = FileAction::Meownew(free_path(&entry.filepath, "meownew", root)?);
+        }
+        ctx.skipped = Some(Skipped::NoUpgrade);
+    }
+    Ok(())
+}
+
+/// Records `package` as one of the packages that ship a directory
+fn add_dir_owner(
+    entry: &MeowZipEntry,
+    package: &str,
+    files_table: &mut Table<&str, &[u8]>,
+    dirs_table: &mut Table<&str, &[u8]>,
+) -> eyre::Result<()> {
+    let path = entry.filepath.to_str().unwrap();
+    let record = dirs_table.get(path)?.map(|row| DirRecord::from(row.value()));
+    let mut record = record.unwrap_or_else(|| DirRecord {
+        mode: entry.mode,
+        uid: entry.uid,
+        gid: entry.gid,
+        owners: vec![],
+    });
+    // Databases written before `DIRS` existed kept a single owner in `FILES`
+    let legacy = files_table.remove(path)?.map(|row| FileRecord::from(row.value()).package);
+    for owner in legacy.iter().map(String::as_str).chain([package]) {
+        if !record.owners.iter().any(|other| other == owner) {
+            record.owners.push(owner.to_owned());
+        }
+    }
+    let row = bincode::encode_to_vec(&record, bincode::config::standard())?;
+    dirs_table.insert(path, &*row)?;
+    Ok(())
+}
+
+/// Warns if a directory is already shipped by other packages with a different mode or owner
+fn check_dir_owners(
+    pkgmeta: &MeowZipMetadata,
+    entry: &MeowZipEntry,
+    dirs_table: &ReadOnlyTable<&str, &[u8]>,
+) -> eyre::Result<()> {
+    let Some(row) = dirs_table.get(entry.filepath.to_str().unwrap())? else {
+        return Ok(());
+    };
+    let record = DirRecord::from(row.value());
+    if let Some(owner) = record.owners.iter().find(|owner| **owner != pkgmeta.name) {
+        let recorded = MeowZipEntry {
+            filepath: entry.filepath.clone(),
+            size: 0,
+            mode: record.mode,
+            uid: record.uid,
+            gid: record.gid,
+            checksum: 0,
+        };
+        warn_dir_mismatch(entry, &pkgmeta.name, &recorded, owner);
+    }
+    Ok(())
+}
+
+fn warn_dir_mismatch(entry: &MeowZipEntry, package: &str, other: &MeowZipEntry, owner: &str) {
+    if (entry.mode, entry.uid, entry.gid) == (other.mode, other.uid, other.gid) {
+        return;
+    }
+    println!(
+        "warning: `{}` is shipped as {:04o} {}:{} by `{}` but as {:04o} {}:{} by `{}`",
+        entry.filepath.display(),
+        other.mode & 0o7777,
+        other.uid,
+        other.gid,
+        owner,
+        entry.mode & 0o7777,
+        entry.uid,
+        entry.gid,
+        package
+    );
+}
+
+/// Refuses to replace a file or symlink that exists but no package owns, unless it matches one of
+/// `overwrite_paths`. With `adopt` such a file is kept instead and recorded as locally modified.
+fn check_untracked(
+    entry: &MeowZipEntry,
+    ctx: &mut PathContext,
+    overwrite_paths: &[Pattern],
+    adopt: bool,
+) -> eyre::Result<()> {
+    if ctx.oldmeta.is_none()
+        || ctx.oldrecord.is_some()
+        || ctx.skipped.is_some()
+        || ctx.filetype.is_directory()
+        || matches!(ctx.action, FileAction::Keep { modified: false })
+    {
+        return Ok(());
+    }
+    if overwrite_paths.iter().any(|pattern| pattern.matches_path(&entry.filepath)) {
+        ctx.action = FileAction::Replace;
+        return Ok(());
+    }
+    if adopt && ctx.filetype.is_regular_file() {
+        ctx.action = FileAction::Keep { modified: true };
+        ctx.skipped = Some(Skipped::Adopted);
+        return Ok(());
+    }
+    bail!(
+        "conflict: `{}` exists but is not owned by any package, use `--overwrite-paths` to replace it{}",
+        entry.filepath.display(),
+        if ctx.filetype.is_regular_file() { " or `--adopt` to keep it" } else { "" }
+    );
 }
 
 fn check_conflicts(
     pkgmeta: &MeowZipMetadata,
     entry: &MeowZipEntry,
     ctx: &PathContext,
+    changing: &[&str],
 ) -> eyre::Result<()> {
-    let alreadyowned =
-        ctx.oldrecord.as_ref().is_some_and(|oldrecord| oldrecord.package != pkgmeta.name);
+    let alreadyowned = ctx.oldrecord.as_ref().is_some_and(|oldrecord| {
+        oldrecord.package != pkgmeta.name && !changing.contains(&oldrecord.package.as_str())
+    });
     if (ctx.filetype.is_symbolic_link() || ctx.filetype.is_regular_file()) && alreadyowned {
         bail!(
             "conflict: `{}` is already owned by package `{}`",
@@ -265,7 +1118,7 @@ fn check_conflicts(
         let oldmetafiletype = oldmetamode.file_type().unwrap();
         oldrecordfiletype == oldmetafiletype
     });
-    if upgradable {
+    if upgradable || matches!(ctx.action, FileAction::Skip) {
         return Ok(());
     }
     match &ctx.filetype {
@@ -288,3 +1141,233 @@ fn check_conflicts(
     }
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::process;
+
+    use redb::Database;
+    use redb::backends::InMemoryBackend;
+
+    use super::*;
+
+    /// A fresh directory to install into
+    pub(crate) fn root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meow-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("etc")).unwrap();
+        dir
+    }
+
+    pub(crate) fn package(name: &str) -> MeowZipMetadata {
+        MeowZipMetadata { name: name.to_owned(), ..Default::default() }
+    }
+
+    /// An entry for a regular file that ships `content`
+    pub(crate) fn entry(root: &Path, filepath: &str, content: &str) -> MeowZipEntry {
+        let scratch = root.join("scratch");
+        fs::write(&scratch, content).unwrap();
+        let checksum = libmeow::file_checksum(&scratch).unwrap();
+        fs::remove_file(scratch).unwrap();
+        MeowZipEntry {
+            filepath: PathBuf::from(filepath),
+            size: content.len() as u64,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            checksum,
+        }
+    }
+
+    /// A files table holding `records`
+    pub(crate) fn files_table(
+        records: &[(&str, &FileRecord)],
+    ) -> ReadOnlyTable<&'static str, &'static [u8]> {
+        let db = Database::builder().create_with_backend(InMemoryBackend::new()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(meowdb::FILES).unwrap();
+            for (path, record) in records {
+                let row = bincode::encode_to_vec(record, bincode::config::standard()).unwrap();
+                table.insert(path, row.as_slice()).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+        db.begin_read().unwrap().open_table(meowdb::FILES).unwrap()
+    }
+
+    #[test]
+    fn test_three_way() {
+        let root = root("three-way");
+        let conf = MeowZipMetadata { backup: vec!["/etc/*.conf".to_owned()], ..package("foo") };
+        let old = entry(&root, "/etc/foo.conf", "1.0");
+        let record = FileRecord::from(&old).with_package("foo".to_owned());
+        let files_table = files_table(&[("/etc/foo.conf", &record), ("/etc/foo", &record)]);
+        let action = |filepath: &str, current: &str, new: &str| {
+            fs::write(path_chroot(filepath, &root), current).unwrap();
+            let new = entry(&root, filepath, new);
+            get_path_context(&conf, &new, &files_table, &root).unwrap().action
+        };
+
+        assert!(matches!(
+            action("/etc/foo.conf", "1.0", "1.0"),
+            FileAction::Keep { modified: false }
+        ));
+        assert!(matches!(
+            action("/etc/foo.conf", "2.0", "2.0"),
+            FileAction::Keep { modified: false }
+        ));
+        assert!(matches!(action("/etc/foo.conf", "1.0", "2.0"), FileAction::Replace));
+        assert!(matches!(
+            action("/etc/foo.conf", "local", "1.0"),
+            FileAction::Keep { modified: true }
+        ));
+        assert!(matches!(
+            action("/etc/foo.conf", "local", "2.0"),
+            FileAction::Meownew(path) if path == Path::new("/etc/foo.conf.meownew")
+        ));
+        // Local changes to files that are not backup files are overwritten
+        assert!(matches!(action("/etc/foo", "local", "2.0"), FileAction::Replace));
+    }
+
+    #[test]
+    fn test_apply_path_policies() {
+        let root = root("path-policies");
+        fs::create_dir_all(root.join("usr/doc")).unwrap();
+        let pkgmeta = package("foo");
+        let config = Config {
+            no_upgrade: vec![Pattern::new("/etc/*").unwrap()],
+            no_extract: vec![Pattern::new("/usr/doc/*").unwrap()],
+            ..Default::default()
+        };
+        let old = entry(&root, "/etc/foo", "1.0");
+        let record = FileRecord::from(&old).with_package("foo".to_owned());
+        let files_table = files_table(&[("/etc/foo", &record)]);
+        let context = |entry: &MeowZipEntry| {
+            let mut ctx = get_path_context(&pkgmeta, entry, &files_table, &root).unwrap();
+            apply_path_policies(entry, &mut ctx, &config, &root).unwrap();
+            ctx
+        };
+
+        // A file that does not exist yet is created even if `NoUpgrade` matches it
+        let ctx = context(&entry(&root, "/etc/foo", "2.0"));
+        assert!(matches!(ctx.action, FileAction::Create));
+        assert_eq!(ctx.skipped, None);
+
+        fs::write(root.join("etc/foo"), "1.0").unwrap();
+        let ctx = context(&entry(&root, "/etc/foo", "2.0"));
+        assert!(
+            matches!(&ctx.action, FileAction::Meownew(path) if path == Path::new("/etc/foo.meownew"))
+        );
+        assert_eq!(ctx.skipped, Some(Skipped::NoUpgrade));
+        let ctx = context(&entry(&root, "/etc/foo", "1.0"));
+        assert!(matches!(ctx.action, FileAction::Keep { modified: false }));
+        assert_eq!(ctx.skipped, None);
+
+        let ctx = context(&entry(&root, "/usr/doc/foo", "doc"));
+        assert!(matches!(ctx.action, FileAction::Skip));
+        assert_eq!(ctx.skipped, Some(Skipped::NoExtract));
+        // Directories are always created
+        let mut dir = entry(&root, "/usr/doc/foo", "");
+        dir.mode = 0o040755;
+        let ctx = context(&dir);
+        assert!(matches!(ctx.action, FileAction::CreateDir));
+        assert_eq!(ctx.skipped, None);
+    }
+
+    #[test]
+    fn test_hand_off() {
+        let root = root("hand-off");
+        let mut dir = entry(&root, "/etc", "");
+        dir.mode = 0o040755;
+        let oldfoo = MeowZipMetadata {
+            filelist: vec![
+                dir.clone(),
+                entry(&root, "/etc/a", "a"),
+                entry(&root, "/etc/b", "b"),
+                entry(&root, "/etc/c", "c"),
+            ],
+            ..package("foo")
+        };
+        fs::write(root.join("etc/b"), "b").unwrap();
+        let record = FileRecord::from(&oldfoo.filelist[2]).with_package("foo".to_owned());
+        let files_table = files_table(&[("/etc/b", &record)]);
+
+        // `bar` may take `/etc/b` over only while `foo` changes in the same transaction
+        let bar = MeowZipMetadata { filelist: vec![entry(&root, "/etc/b", "b")], ..package("bar") };
+        let ctx = get_path_context(&bar, &bar.filelist[0], &files_table, &root).unwrap();
+        check_conflicts(&bar, &bar.filelist[0], &ctx, &["bar", "foo"]).unwrap();
+        let error = check_conflicts(&bar, &bar.filelist[0], &ctx, &["bar"]).unwrap_err();
+        assert_eq!(error.to_string(), "conflict: `/etc/b` is already owned by package `foo`");
+        assert_eq!(file_record(&bar, &bar.filelist[0], &ctx, &root).unwrap().package, "bar");
+
+        // The new `foo` ships `/etc` and `/etc/a` again, `/etc/b` moved and only `/etc/c` goes
+        let newfoo =
+            MeowZipMetadata { filelist: vec![dir, entry(&root, "/etc/a", "a")], ..package("foo") };
+        let mut shipped = HashMap::new();
+        for pkgmeta in [&newfoo, &bar] {
+            for entry in &pkgmeta.filelist {
+                shipped.insert(entry.filepath.as_path(), (pkgmeta.name.as_str(), entry));
+            }
+        }
+        let kept: Vec<_> = oldfoo
+            .filelist
+            .iter()
+            .map(|entry| still_shipped(entry, Some(&newfoo), &shipped))
+            .collect();
+        assert_eq!(kept, [true, true, true, false]);
+    }
+
+    #[test]
+    fn test_check_untracked() {
+        let root = root("check-untracked");
+        let pkgmeta = package("foo");
+        fs::write(root.join("etc/foo"), "local").unwrap();
+        let entry = entry(&root, "/etc/foo", "packaged");
+        let files_table = files_table(&[]);
+
+        let mut ctx = get_path_context(&pkgmeta, &entry, &files_table, &root).unwrap();
+        let error = check_untracked(&entry, &mut ctx, &[], false).unwrap_err();
+        assert!(error.to_string().contains("not owned by any package"));
+
+        let overwrite_paths = [Pattern::new("/etc/*").unwrap()];
+        check_untracked(&entry, &mut ctx, &overwrite_paths, false).unwrap();
+        assert!(matches!(ctx.action, FileAction::Replace));
+
+        // A file that already matches the package is no conflict
+        fs::write(root.join("etc/foo"), "packaged").unwrap();
+        let mut ctx = get_path_context(&pkgmeta, &entry, &files_table, &root).unwrap();
+        check_untracked(&entry, &mut ctx, &[], false).unwrap();
+        assert!(matches!(ctx.action, FileAction::Keep { modified: false }));
+    }
+
+    #[test]
+    fn test_adopt_then_upgrade() {
+        let root = root("adopt-upgrade");
+        let pkgmeta = package("foo");
+        fs::write(root.join("etc/foo"), "local").unwrap();
+        let old = entry(&root, "/etc/foo", "1.0");
+        let mut ctx = get_path_context(&pkgmeta, &old, &files_table(&[]), &root).unwrap();
+        check_untracked(&old, &mut ctx, &[], true).unwrap();
+        assert!(matches!(ctx.action, FileAction::Keep { modified: true }));
+        let record = file_record(&pkgmeta, &old, &ctx, &root).unwrap();
+        assert_eq!(record.skipped, Some(Skipped::Adopted));
+        assert_eq!(record.checksum, libmeow::file_checksum(&root.join("etc/foo")).unwrap());
+
+        // The upgrade leaves the adopted file alone even though it is not a backup file
+        let new = entry(&root, "/etc/foo", "2.0");
+        let files_table = files_table(&[("/etc/foo", &record)]);
+        let ctx = get_path_context(&pkgmeta, &new, &files_table, &root).unwrap();
+        assert!(
+            matches!(&ctx.action, FileAction::Meownew(path) if path == Path::new("/etc/foo.meownew"))
+        );
+        assert_eq!(ctx.skipped, Some(Skipped::Adopted));
+        assert_eq!(fs::read_to_string(root.join("etc/foo")).unwrap(), "local");
+
+        // Once the package ships the same file it is the package's again
+        fs::write(root.join("etc/foo"), "2.0").unwrap();
+        let ctx = get_path_context(&pkgmeta, &new, &files_table, &root).unwrap();
+        assert!(matches!(ctx.action, FileAction::Keep { modified: false }));
+        assert_eq!(ctx.skipped, None);
+    }
+}
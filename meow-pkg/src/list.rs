@@ -1,16 +1,24 @@
-use std::path::PathBuf;
-
-use libmeow::meowdb;
+use libmeow::config::Config;
+use libmeow::{lock, meowdb};
 use redb::{ReadableDatabase, ReadableTable};
 
-pub fn list(root: PathBuf) -> eyre::Result<()> {
-    let db = meowdb::open(&root)?;
+pub fn list(config: &Config) -> eyre::Result<()> {
+    let _lock = lock::shared(&config.db_path)?;
+    let db = meowdb::open_read_only(&config.db_path)?;
     let read_txn = db.begin_read()?;
     let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
+    let holds_table = meowdb::open_optional_table(&read_txn, meowdb::HOLDS)?;
     for result in pkgs_table.iter()? {
         let (_, value) = result?;
         let metadata = libmeow::meowzip::MeowZipMetadata::from(value.value());
-        println!("{}-{}-{}.mz", metadata.name, metadata.version, metadata.release);
+        let held = meowdb::is_held(holds_table.as_ref(), &metadata.name)?;
+        println!(
+            "{}-{}-{}.mz{}",
+            metadata.name,
+            metadata.version,
+            metadata.release,
+            if held { " (held)" } else { "" }
+        );
     }
     Ok(())
 }
@@ -1,12 +1,24 @@
 #![feature(exit_status_error)]
 
+mod autoremove;
+mod check;
 mod cli;
+mod hold;
 mod info;
 mod install;
 mod list;
+mod mark;
+mod merge;
+mod query;
 mod remove;
+mod search;
+mod upgrade;
 
 fn main() -> eyre::Result<()> {
-    color_eyre::install()?;
     cli::run()
 }
+
+/// Prints a warning that libmeow passed up
+fn warn(message: &str) {
+    eprintln!("warning: {}", message);
+}
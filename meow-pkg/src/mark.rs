@@ -0,0 +1,32 @@
+use eyre::bail;
+use libmeow::config::Config;
+use libmeow::meowdb::{self, InstallReason};
+use libmeow::{ensure_superuser, lock};
+use redb::ReadableTable;
+
+use crate::warn;
+
+pub fn mark(packages: Vec<String>, reason: InstallReason, config: &Config) -> eyre::Result<()> {
+    ensure_superuser()?;
+    let _lock = lock::exclusive(&config.db_path, warn)?;
+    let db = meowdb::open(&config.db_path)?;
+    let write_txn = db.begin_write()?;
+    {
+        let pkgs_table = write_txn.open_table(meowdb::PACKAGES)?;
+        let mut reasons_table = write_txn.open_table(meowdb::REASONS)?;
+        for name in &packages {
+            if pkgs_table.get(name.as_str())?.is_none() {
+                bail!("Package `{}` is not installed", name);
+            }
+            reason.set(&mut reasons_table, name)?;
+            match reason {
+                InstallReason::Explicit => println!("Marked `{}` as explicitly installed", name),
+                InstallReason::Dependency => {
+                    println!("Marked `{}` as installed as a dependency", name)
+                }
+            }
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+use eyre::Context;
+use libmeow::config::Config;
+use libmeow::{
+    LITERAL_STYLE,
+    columned,
+    confirm,
+    ensure_superuser,
+    lock,
+    meowdb,
+    path_chroot,
+    styled,
+};
+use redb::{Database, ReadableDatabase, ReadableTable};
+use similar::TextDiff;
+
+use crate::warn;
+
+pub fn merge(root: PathBuf, config: &Config) -> eyre::Result<()> {
+    ensure_superuser()?;
+    let _lock = lock::exclusive(&config.db_path, warn)?;
+    let db = meowdb::open(&config.db_path)?;
+    let mut pending = vec![];
+    {
+        let read_txn = db.begin_read()?;
+        if let Some(merges_table) = meowdb::open_optional_table(&read_txn, meowdb::MERGES)? {
+            for row in merges_table.iter()? {
+                let (new, target) = row?;
+                pending.push((PathBuf::from(new.value()), PathBuf::from(target.value())));
+            }
+        }
+    }
+
+    // Files deleted by hand are no longer pending
+    let (pending, gone): (Vec<_>, Vec<_>) =
+        pending.into_iter().partition(|(new, _)| path_chroot(new, &root).exists());
+    for (new, _) in &gone {
+        resolve(&db, new)?;
+    }
+    if pending.is_empty() {
+        println!("No configuration files are waiting to be merged");
+        return Ok(());
+    }
+    let rows: Vec<_> = pending
+        .iter()
+        .map(|(new, target)| vec![new.display().to_string(), target.display().to_string()])
+        .collect();
+    columned::print_table(&["Pending", "Replaces"], &rows);
+    println!();
+
+    for (new, target) in &pending {
+        if merge_file(new, target, &root)? {
+            resolve(&db, new)?;
+        }
+    }
+    Ok(())
+}
+
+/// Shows how a pending file differs from the file it is meant to replace and asks what to do
+/// with it, returns whether it was dealt with
+fn merge_file(new: &Path, target: &Path, root: &Path) -> eyre::Result<bool> {
+    let new_dest = path_chroot(new, root);
+    let target_dest = path_chroot(target, root);
+    let current = String::from_utf8_lossy(&fs::read(&target_dest).unwrap_or_default()).into_owned();
+    let proposed = String::from_utf8_lossy(&fs::read(&new_dest)?).into_owned();
+    print!(
+        "{}",
+        TextDiff::from_lines(&current, &proposed)
+            .unified_diff()
+            .header(&target.display().to_string(), &new.display().to_string())
+    );
+    loop {
+        print!(
+            "{} ",
+            styled(
+                &format!(
+                    "`{}`: [k]eep current, [r]eplace with new, [m]erge with $MERGETOOL or [s]kip?",
+                    target.display()
+                ),
+                LITERAL_STYLE
+            )
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            println!();
+            return Ok(false);
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "k" | "keep" => {
+                fs::remove_file(&new_dest)?;
+                return Ok(true);
+            }
+            "r" | "replace" => {
+                fs::rename(&new_dest, &target_dest)?;
+                return Ok(true);
+            }
+            "m" | "merge" => {
+                let Ok(mergetool) = env::var("MERGETOOL") else {
+                    println!("warning: `MERGETOOL` is not set");
+                    continue;
+                };
+                let mut args = mergetool.split_whitespace();
+                let Some(program) = args.next() else {
+                    println!("warning: `MERGETOOL` is empty");
+                    continue;
+                };
+                let status = Command::new(program)
+                    .args(args)
+                    .arg(&target_dest)
+                    .arg(&new_dest)
+                    .status()
+                    .with_context(|| format!("Failed to run `{}`", mergetool))?;
+                if !status.success() {
+                    println!("warning: `{}` exited with {}", mergetool, status);
+                    continue;
+                }
+                if confirm(&format!("Delete `{}` now that it is merged?", new.display()))? {
+                    fs::remove_file(&new_dest)?;
+                    return Ok(true);
+                }
+            }
+            "s" | "skip" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// Forgets about a pending file
+fn resolve(db: &Database, new: &Path) -> eyre::Result<()> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut merges_table = write_txn.open_table(meowdb::MERGES)?;
+        merges_table.remove(new.to_str().unwrap())?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
@@ -0,0 +1,290 @@
+use std::slice;
+
+use eyre::bail;
+use libmeow::config::Config;
+use libmeow::meowdb::{self, InstallReason};
+use libmeow::meowzip::MeowZipMetadata;
+use libmeow::repo::{RepoIndex, Repository, find_newest};
+use libmeow::version::Dependency;
+use libmeow::{columned, lock};
+use redb::{ReadableDatabase, ReadableTable};
+
+use crate::install::load_repositories;
+
+/// Installed packages, and the repository packages that can stand in for missing ones
+struct Packages<'a> {
+    installed: &'a [MeowZipMetadata],
+    repos: &'a [(Repository, RepoIndex)],
+}
+
+impl<'a> Packages<'a> {
+    /// Finds the package a dependency refers to, along with the name of its repository if it
+    /// is not installed. The package it names is preferred over one that provides it.
+    fn find(&self, dependency: &Dependency) -> Option<(&'a MeowZipMetadata, Option<&'a str>)> {
+        if let Some(pkgmeta) =
+            self.installed.iter().find(|pkgmeta| pkgmeta.name == dependency.name).or_else(|| {
+                self.installed.iter().find(|pkgmeta| dependency.satisfied_by_package(pkgmeta))
+            })
+        {
+            return Some((pkgmeta, None));
+        }
+        let name = match find_newest(self.repos, &dependency.name) {
+            Some(_) => &dependency.name,
+            None => {
+                &self
+                    .repos
+                    .iter()
+                    .flat_map(|(_, index)| &index.packages)
+                    .find(|package| dependency.satisfied_by_package(&package.metadata))?
+                    .metadata
+                    .name
+            }
+        };
+        find_newest(self.repos, name)
+            .map(|(repo, package)| (&package.metadata, Some(repo.name.as_str())))
+    }
+}
+
+pub fn tree(
+    package: String,
+    depth: Option<usize>,
+    refresh: bool,
+    config: &Config,
+) -> eyre::Result<()> {
+    let _lock = lock::shared(&config.db_path)?;
+    let db = meowdb::open_read_only(&config.db_path)?;
+    let installed = read_installed(&db)?;
+    let repos = load_repositories(&db, config, refresh)?;
+    let packages = Packages { installed: &installed, repos: &repos };
+    let Some(top) = packages.find(&Dependency::parse(&package)) else {
+        bail!("Package `{}` is not installed or available in any repository", package);
+    };
+    println!("{}", tree_label(top));
+    let mut shown = vec![top.0.name.clone()];
+    print_subtree(top.0, "", depth.unwrap_or(usize::MAX), &packages, &mut shown);
+    Ok(())
+}
+
+/// Prints the dependencies of a package below it, expanding each package only the first time
+/// it is shown
+fn print_subtree(
+    pkgmeta: &MeowZipMetadata,
+    prefix: &str,
+    depth: usize,
+    packages: &Packages,
+    shown: &mut Vec<String>,
+) {
+    if depth == 0 {
+        return;
+    }
+    for (i, depend) in pkgmeta.depends.iter().enumerate() {
+        let (branch, indent) = if i + 1 == pkgmeta.depends.len() {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        let Some(dependency) = packages.find(&Dependency::parse(depend)) else {
+            println!("{}{}{} [missing]", prefix, branch, depend);
+            continue;
+        };
+        if shown.contains(&dependency.0.name) {
+            println!("{}{}{} (already shown)", prefix, branch, tree_label(dependency));
+            continue;
+        }
+        shown.push(dependency.0.name.clone());
+        println!("{}{}{}", prefix, branch, tree_label(dependency));
+        print_subtree(dependency.0, &format!("{}{}", prefix, indent), depth - 1, packages, shown);
+    }
+}
+
+fn tree_label((pkgmeta, repo): (&MeowZipMetadata, Option<&str>)) -> String {
+    match repo {
+        Some(repo) => {
+            format!("{} {}-{} [{}]", pkgmeta.name, pkgmeta.version, pkgmeta.release, repo)
+        }
+        None => format!("{} {}-{}", pkgmeta.name, pkgmeta.version, pkgmeta.release),
+    }
+}
+
+pub fn rdeps(package: String, config: &Config) -> eyre::Result<()> {
+    let _lock = lock::shared(&config.db_path)?;
+    let installed = read_installed(&meowdb::open_read_only(&config.db_path)?)?;
+    if !installed.iter().any(|pkgmeta| pkgmeta.name == package) {
+        bail!("Package `{}` is not installed", package);
+    }
+    let rows: Vec<_> = reverse_dependencies(&installed, slice::from_ref(&package))
+        .into_iter()
+        .map(|(dependant, dependency)| vec![dependant, dependency])
+        .collect();
+    if rows.is_empty() {
+        println!("No installed package depends on `{}`", package);
+        return Ok(());
+    }
+    columned::print_table(&["Package", "Depends On"], &rows);
+    Ok(())
+}
+
+pub fn why(package: String, config: &Config) -> eyre::Result<()> {
+    let _lock = lock::shared(&config.db_path)?;
+    let db = meowdb::open_read_only(&config.db_path)?;
+    let installed = read_installed(&db)?;
+    let read_txn = db.begin_read()?;
+    let reasons_table = meowdb::open_optional_table(&read_txn, meowdb::REASONS)?;
+    if !installed.iter().any(|pkgmeta| pkgmeta.name == package) {
+        bail!("Package `{}` is not installed", package);
+    }
+    if InstallReason::get(reasons_table.as_ref(), &package)? == InstallReason::Explicit {
+        println!("`{}` was installed explicitly", package);
+        return Ok(());
+    }
+
+    let mut explicit = vec![];
+    for pkgmeta in &installed {
+        if InstallReason::get(reasons_table.as_ref(), &pkgmeta.name)? == InstallReason::Explicit {
+            explicit.push(pkgmeta.name.as_str());
+        }
+    }
+    let chains = dependency_chains(&installed, &explicit, &package);
+    if chains.is_empty() {
+        println!(
+            "`{}` was installed as a dependency but no explicitly installed package needs it anymore, `autoremove` would remove it",
+            package
+        );
+    }
+    for chain in chains {
+        println!("{}", chain);
+    }
+    Ok(())
+}
+
+/// Finds how the packages in `explicit` need an installed package, as chains that start with one
+/// of them and end with the package, the shortest first
+fn dependency_chains(
+    installed: &[MeowZipMetadata],
+    explicit: &[&str],
+    package: &str,
+) -> Vec<String> {
+    // Breadth-first search from the package towards the explicitly installed packages that need
+    // it, each entry is a package and the index of the entry it depends on
+    let mut visited = vec![(package, None)];
+    let mut chains = vec![];
+    let mut i = 0;
+    while i < visited.len() {
+        let dependency = installed.iter().find(|pkgmeta| pkgmeta.name == visited[i].0).unwrap();
+        for pkgmeta in installed {
+            if visited.iter().any(|(other, _)| *other == pkgmeta.name)
+                || !depends_on(pkgmeta, dependency)
+            {
+                continue;
+            }
+            visited.push((&pkgmeta.name, Some(i)));
+            if explicit.contains(&pkgmeta.name.as_str()) {
+                let mut chain = vec![];
+                let mut next = Some(visited.len() - 1);
+                while let Some(j) = next {
+                    chain.push(visited[j].0);
+                    next = visited[j].1;
+                }
+                chains.push(chain.join(" -> "));
+            }
+        }
+        i += 1;
+    }
+    chains
+}
+
+/// Whether a package depends on another one, by its name or by something it provides
+pub fn depends_on(pkgmeta: &MeowZipMetadata, other: &MeowZipMetadata) -> bool {
+    pkgmeta.depends.iter().any(|dep| Dependency::parse(dep).satisfied_by_package(other))
+}
+
+/// Finds the installed packages that depend on any of `names`, directly or through other
+/// packages. Returns each one with the name of the package it depends on, in the order they
+/// are found.
+pub fn reverse_dependencies(
+    installed: &[MeowZipMetadata],
+    names: &[String],
+) -> Vec<(String, String)> {
+    let mut found = names.to_vec();
+    let mut dependants = vec![];
+    let mut i = 0;
+    while i < found.len() {
+        let name = found[i].clone();
+        let Some(dependency) = installed.iter().find(|pkgmeta| pkgmeta.name == name) else {
+            i += 1;
+            continue;
+        };
+        for pkgmeta in installed {
+            if !found.contains(&pkgmeta.name) && depends_on(pkgmeta, dependency) {
+                found.push(pkgmeta.name.clone());
+                dependants.push((pkgmeta.name.clone(), name.clone()));
+            }
+        }
+        i += 1;
+    }
+    dependants
+}
+
+fn read_installed(db: &impl ReadableDatabase) -> eyre::Result<Vec<MeowZipMetadata>> {
+    let read_txn = db.begin_read()?;
+    let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
+    let mut installed = vec![];
+    for row in pkgs_table.iter()? {
+        installed.push(MeowZipMetadata::from(row?.1.value()));
+    }
+    Ok(installed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, depends: &[&str], provides: &[&str]) -> MeowZipMetadata {
+        MeowZipMetadata {
+            name: name.to_owned(),
+            depends: depends.iter().map(|&depend| depend.to_owned()).collect(),
+            provides: provides.iter().map(|&provide| provide.to_owned()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn installed() -> Vec<MeowZipMetadata> {
+        vec![
+            package("app", &["lib", "sh"], &[]),
+            package("lib", &["libc"], &[]),
+            package("libc", &[], &[]),
+            package("bash", &["libc"], &["sh"]),
+            package("tool", &["bash"], &[]),
+        ]
+    }
+
+    #[test]
+    fn test_reverse_dependencies() {
+        let installed = installed();
+        assert_eq!(
+            reverse_dependencies(&installed, &["libc".to_owned()]),
+            [
+                ("lib".to_owned(), "libc".to_owned()),
+                ("bash".to_owned(), "libc".to_owned()),
+                ("app".to_owned(), "lib".to_owned()),
+                ("tool".to_owned(), "bash".to_owned()),
+            ]
+        );
+        assert!(reverse_dependencies(&installed, &["app".to_owned()]).is_empty());
+    }
+
+    #[test]
+    fn test_dependency_chains() {
+        let installed = installed();
+        assert_eq!(
+            dependency_chains(&installed, &["app", "tool"], "libc"),
+            ["app -> lib -> libc", "tool -> bash -> libc"]
+        );
+        // Provided dependencies count, and only the shortest chain from each package is shown
+        assert_eq!(
+            dependency_chains(&installed, &["app", "tool"], "bash"),
+            ["app -> bash", "tool -> bash"]
+        );
+        assert!(dependency_chains(&installed, &["lib"], "bash").is_empty());
+    }
+}
@@ -2,70 +2,301 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use eyre::bail;
-use libmeow::meowzip::MeowZipMetadata;
-use libmeow::{columned, ensure_superuser, meowdb, path_chroot};
-use redb::{ReadableDatabase, ReadableTable, Table};
+use file_mode::Mode;
+use libmeow::config::Config;
+use libmeow::meowdb::{DirRecord, FileRecord, Skipped};
+use libmeow::meowzip::{MeowZipEntry, MeowZipMetadata};
+use libmeow::version::Dependency;
+use libmeow::{columned, confirm, ensure_superuser, lock, meowdb, path_chroot};
+use redb::{ReadOnlyTable, ReadableDatabase, ReadableTable, Table};
 
-use crate::install::run_hook;
+use crate::cli::RemoveOptions;
+use crate::install::{format_size_change, free_path, is_backup, is_dir, run_hook};
+use crate::warn;
 
-pub fn remove(name: String, breakdeps: bool, root: PathBuf) -> eyre::Result<()> {
-    ensure_superuser()?;
-    let db = meowdb::open(&root)?;
-    let read_txn = db.begin_read()?;
-    let packages = read_txn.open_table(meowdb::PACKAGES)?;
-    let Some(pkgmeta): Option<MeowZipMetadata> =
-        packages.get(name.as_str())?.map(|row| row.value().into())
-    else {
-        bail!("Package `{}` is not installed", name);
+pub fn remove(
+    names: Vec<String>,
+    options: RemoveOptions,
+    root: PathBuf,
+    config: &Config,
+) -> eyre::Result<()> {
+    if !options.dry_run {
+        ensure_superuser()?;
+    }
+    let _lock = if options.dry_run {
+        lock::shared(&config.db_path)?
+    } else {
+        lock::exclusive(&config.db_path, warn)?
     };
+    remove_locked(&names, &options, &root, config)
+}
 
-    let mut dependants = vec![];
+/// Removes packages like `remove`, for callers that already hold the lock. With `cascade` the
+/// packages that depend on them, directly or not, are removed too.
+pub fn remove_locked(
+    names: &[String],
+    options: &RemoveOptions,
+    root: &Path,
+    config: &Config,
+) -> eyre::Result<()> {
+    let RemoveOptions { breakdeps, cascade, dry_run, noconfirm, ignore_hold } = *options;
+    let db = meowdb::Db::open(&config.db_path, dry_run)?;
+    let read_txn = db.begin_read()?;
+    let packages = read_txn.open_table(meowdb::PACKAGES)?;
+    let mut installed = vec![];
     for row in packages.iter()? {
-        let (depname, depmeta) = row?;
-        let depmeta = MeowZipMetadata::from(depmeta.value());
-        if depmeta.depends.iter().any(|dep| dep == &name) {
-            dependants.push(depname.value().to_owned());
+        installed.push(MeowZipMetadata::from(row?.1.value()));
+    }
+
+    let mut names = names.to_vec();
+    for name in &names {
+        if !installed.iter().any(|pkgmeta| &pkgmeta.name == name) {
+            bail!("Package `{}` is not installed", name);
         }
     }
+    let cascaded = if cascade { add_dependants(&installed, &mut names) } else { vec![] };
+    let dependants = find_dependants(&installed, &names);
+    let pkgmetas: Vec<_> =
+        installed.into_iter().filter(|pkgmeta| names.contains(&pkgmeta.name)).collect();
+    let holds_table = meowdb::open_optional_table(&read_txn, meowdb::HOLDS)?;
+    for pkgmeta in &pkgmetas {
+        if !ignore_hold && meowdb::is_held(holds_table.as_ref(), &pkgmeta.name)? {
+            bail!("Package `{}` is held, use `--ignore-hold` to remove it anyway", pkgmeta.name);
+        }
+    }
+
     if !dependants.is_empty() && !breakdeps {
-        println!("The following packages depend on `{}`:", name);
-        columned::print(&dependants);
+        columned::print_table(&["Package", "Depends On"], &dependants);
+        println!();
         bail!(
-            "Cannot remove package `{}` because other packages depend on it, use `--breakdeps` to remove anyway",
-            name
+            "Cannot remove packages that other packages depend on, use `--cascade` to remove them too or `--breakdeps` to remove anyway"
         );
     }
+    if !cascaded.is_empty() {
+        println!(
+            "The following packages depend on the packages being removed and are removed too:"
+        );
+        columned::print(&cascaded);
+        println!();
+    }
+
+    let order = removal_order(&pkgmetas);
 
-    if &root == "/" {
-        run_hook(&pkgmeta.name, &pkgmeta.pre_remove, "pre-remove", &pkgmeta.version, "")?;
+    if dry_run {
+        let files_table = read_txn.open_table(meowdb::FILES)?;
+        let dirs_table = meowdb::open_optional_table(&read_txn, meowdb::DIRS)?;
+        let removing: Vec<_> = pkgmetas.iter().map(|pkgmeta| pkgmeta.name.as_str()).collect();
+        let mut size_change = 0;
+        let mut removed = 0;
+        for &i in &order {
+            let pkgmeta = &pkgmetas[i];
+            println!("Would remove `{}` {}-{}", pkgmeta.name, pkgmeta.version, pkgmeta.release);
+            for entry in pkgmeta.filelist.iter().rev() {
+                let owners = other_owners(dirs_table.as_ref(), &entry.filepath, &removing)?;
+                size_change -=
+                    print_uninstall_plan(root, pkgmeta, &entry.filepath, &owners, &files_table)?;
+            }
+            if root == "/" {
+                for (hook, hook_name) in
+                    [(&pkgmeta.pre_remove, "pre-remove"), (&pkgmeta.post_remove, "post-remove")]
+                {
+                    if !hook.is_empty() {
+                        println!("  run      {} hook", hook_name);
+                    }
+                }
+            }
+            removed += pkgmeta.filelist.iter().filter(|entry| !is_dir(entry)).count();
+        }
+        println!(
+            "Database: {} package records removed, {} file records removed",
+            pkgmetas.len(),
+            removed
+        );
+        println!("Disk usage change: {}", format_size_change(size_change));
+        return Ok(());
+    }
+
+    let mut rows = vec![];
+    let mut total_size_change = 0;
+    for &i in &order {
+        let pkgmeta = &pkgmetas[i];
+        let size: u64 = pkgmeta.filelist.iter().map(|entry| entry.size).sum();
+        total_size_change -= size as i64;
+        rows.push(vec![
+            pkgmeta.name.clone(),
+            format!("{}-{}", pkgmeta.version, pkgmeta.release),
+            format_size_change(-(size as i64)),
+        ]);
+    }
+    columned::print_table(&["Package", "Version", "Size Change"], &rows);
+    println!();
+    println!("Total Size Change: {}", format_size_change(total_size_change));
+    println!();
+    if !noconfirm && !confirm("Proceed with removal?")? {
+        bail!("Removal cancelled");
+    }
+
+    if root == "/" {
+        for &i in &order {
+            let pkgmeta = &pkgmetas[i];
+            run_hook(
+                &config.hook_shell,
+                &pkgmeta.name,
+                &pkgmeta.pre_remove,
+                "pre-remove",
+                &pkgmeta.version,
+                "",
+            )?;
+        }
     }
 
     let write_txn = db.begin_write()?;
     {
         let mut pkgs_table = write_txn.open_table(meowdb::PACKAGES)?;
         let mut files_table = write_txn.open_table(meowdb::FILES)?;
+        let mut dirs_table = write_txn.open_table(meowdb::DIRS)?;
+        let mut reasons_table = write_txn.open_table(meowdb::REASONS)?;
+        let mut holds_table = write_txn.open_table(meowdb::HOLDS)?;
 
-        for entry in pkgmeta.filelist.iter().rev() {
-            uninstall_path(&root, &entry.filepath, &mut files_table)?;
+        for &i in &order {
+            let pkgmeta = &pkgmetas[i];
+            for entry in pkgmeta.filelist.iter().rev() {
+                uninstall_path(root, entry, pkgmeta, &mut files_table, &mut dirs_table)?;
+            }
+            pkgs_table.remove(pkgmeta.name.as_str())?;
+            reasons_table.remove(pkgmeta.name.as_str())?;
+            holds_table.remove(pkgmeta.name.as_str())?;
         }
-        pkgs_table.remove(&*name)?;
     }
     write_txn.commit()?;
 
-    if &root == "/" {
-        run_hook(&pkgmeta.name, &pkgmeta.post_remove, "post-remove", &pkgmeta.version, "")?;
+    if root == "/" {
+        for &i in &order {
+            let pkgmeta = &pkgmetas[i];
+            run_hook(
+                &config.hook_shell,
+                &pkgmeta.name,
+                &pkgmeta.post_remove,
+                "post-remove",
+                &pkgmeta.version,
+                "",
+            )?;
+        }
     }
 
     Ok(())
 }
 
+/// Orders packages so that each one comes before the packages it depends on
+fn removal_order(pkgmetas: &[MeowZipMetadata]) -> Vec<usize> {
+    fn visit(i: usize, pkgmetas: &[MeowZipMetadata], visited: &mut [bool], order: &mut Vec<usize>) {
+        if visited[i] {
+            return;
+        }
+        visited[i] = true;
+        for dependency in pkgmetas[i].depends.iter().map(|dep| Dependency::parse(dep)) {
+            for (j, other) in pkgmetas.iter().enumerate() {
+                if dependency.satisfied_by_package(other) {
+                    visit(j, pkgmetas, visited, order);
+                }
+            }
+        }
+        order.push(i);
+    }
+
+    let mut visited = vec![false; pkgmetas.len()];
+    let mut order = vec![];
+    for i in 0..pkgmetas.len() {
+        visit(i, pkgmetas, &mut visited, &mut order);
+    }
+    order.reverse();
+    order
+}
+
+/// The packages other than `removed` that ship a directory
+pub fn other_owners(
+    dirs_table: Option<&ReadOnlyTable<&str, &[u8]>>,
+    path: &Path,
+    removed: &[&str],
+) -> eyre::Result<Vec<String>> {
+    let Some(dirs_table) = dirs_table else {
+        return Ok(vec![]);
+    };
+    let Some(row) = dirs_table.get(path.to_str().unwrap())? else {
+        return Ok(vec![]);
+    };
+    let mut owners = DirRecord::from(row.value()).owners;
+    owners.retain(|owner| !removed.contains(&owner.as_str()));
+    Ok(owners)
+}
+
+/// Finds the packages that stay installed but lose a dependency when `names` are removed, a
+/// dependency that another remaining package provides is not lost. Returns rows of the
+/// dependant and the dependency.
+fn find_dependants(installed: &[MeowZipMetadata], names: &[String]) -> Vec<Vec<String>> {
+    let (removed, others): (Vec<_>, Vec<_>) =
+        installed.iter().partition(|pkgmeta| names.contains(&pkgmeta.name));
+    let mut dependants = vec![];
+    for other in &others {
+        for dependency in other.depends.iter().map(|dep| Dependency::parse(dep)) {
+            if removed.iter().any(|pkgmeta| dependency.satisfied_by_package(pkgmeta))
+                && !others.iter().any(|pkgmeta| dependency.satisfied_by_package(pkgmeta))
+            {
+                dependants.push(vec![other.name.clone(), dependency.to_string()]);
+            }
+        }
+    }
+    dependants
+}
+
+/// Adds the packages that would lose a dependency to `names` until none would, returns the ones
+/// that were added
+fn add_dependants(installed: &[MeowZipMetadata], names: &mut Vec<String>) -> Vec<String> {
+    let mut cascaded = vec![];
+    loop {
+        let dependants = find_dependants(installed, names);
+        if dependants.is_empty() {
+            return cascaded;
+        }
+        for row in dependants {
+            if !names.contains(&row[0]) {
+                names.push(row[0].clone());
+                cascaded.push(row[0].clone());
+            }
+        }
+    }
+}
+
+/// Removes a path shipped by a package along with its record. A directory that other packages
+/// ship too is kept, only the package is dropped from its owners. A modified backup file is
+/// renamed to `.meowsave` instead of being deleted.
 pub fn uninstall_path(
     root: &Path,
-    path: &Path,
+    entry: &MeowZipEntry,
+    pkgmeta: &MeowZipMetadata,
     files_table: &mut Table<&str, &[u8]>,
+    dirs_table: &mut Table<&str, &[u8]>,
 ) -> eyre::Result<()> {
+    let path = entry.filepath.to_str().unwrap();
+    if Mode::from(entry.mode).file_type().unwrap().is_directory() {
+        let record = dirs_table.get(path)?.map(|row| DirRecord::from(row.value()));
+        if let Some(mut record) = record {
+            record.owners.retain(|owner| *owner != pkgmeta.name);
+            if !record.owners.is_empty() {
+                let row = bincode::encode_to_vec(&record, bincode::config::standard())?;
+                dirs_table.insert(path, &*row)?;
+                return Ok(());
+            }
+            dirs_table.remove(path)?;
+        }
+    }
     let dest = path_chroot(path, root);
-    if fs::exists(&dest)? {
+    if never_extracted(&entry.filepath, files_table)? {
+        // Whatever is at the path was not put there by the package
+    } else if let Some(save) = save_path(root, pkgmeta, &entry.filepath, files_table)? {
+        fs::rename(&dest, path_chroot(&save, root))?;
+        println!("warning: `{}` saved as `{}`", path, save.display());
+    } else if fs::exists(&dest)? {
         let meta = fs::symlink_metadata(&dest)?;
         if meta.is_symlink() || meta.is_file() {
             fs::remove_file(&dest)?;
@@ -73,6 +304,176 @@ pub fn uninstall_path(
             let _ = fs::remove_dir(dest);
         }
     }
-    files_table.remove(path.to_str().unwrap())?;
+    files_table.remove(path)?;
     Ok(())
 }
+
+/// Where a backup file of a package, or a file kept by `NoUpgrade`, is saved when the package
+/// stops shipping it, `None` if it is neither or was not modified since it was installed.
+/// Adopted files were never the package's and are always saved.
+fn save_path<T>(
+    root: &Path,
+    pkgmeta: &MeowZipMetadata,
+    path: &Path,
+    files_table: &T,
+) -> eyre::Result<Option<PathBuf>>
+where
+    T: ReadableTable<&'static str, &'static [u8]>,
+{
+    let dest = path_chroot(path, root);
+    if !fs::symlink_metadata(&dest).is_ok_and(|meta| meta.is_file()) {
+        return Ok(None);
+    }
+    let Some(row) = files_table.get(path.to_str().unwrap())? else {
+        return Ok(None);
+    };
+    let record = FileRecord::from(row.value());
+    if record.skipped != Some(Skipped::Adopted)
+        && (!is_backup(pkgmeta, path) && record.skipped != Some(Skipped::NoUpgrade)
+            || record.checksum == libmeow::file_checksum(&dest)?)
+    {
+        return Ok(None);
+    }
+    Ok(Some(free_path(path, "meowsave", root)?))
+}
+
+/// Whether a `NoExtract` glob kept the file recorded at a path from being extracted
+fn never_extracted<T>(path: &Path, files_table: &T) -> eyre::Result<bool>
+where T: ReadableTable<&'static str, &'static [u8]> {
+    let record = files_table.get(path.to_str().unwrap())?.map(|row| FileRecord::from(row.value()));
+    Ok(record.is_some_and(|record| record.skipped == Some(Skipped::NoExtract)))
+}
+
+/// Prints what `uninstall_path` would do and returns the disk space it would free, `owners`
+/// are the packages that keep shipping the path if it is a directory
+pub fn print_uninstall_plan<T>(
+    root: &Path,
+    pkgmeta: &MeowZipMetadata,
+    path: &Path,
+    owners: &[String],
+    files_table: &T,
+) -> eyre::Result<i64>
+where
+    T: ReadableTable<&'static str, &'static [u8]>,
+{
+    let dest = path_chroot(path, root);
+    if !fs::exists(&dest)? || never_extracted(path, files_table)? {
+        return Ok(0);
+    }
+    if let Some(save) = save_path(root, pkgmeta, path, files_table)? {
+        println!("  save     {} as {}", path.display(), save.display());
+        return Ok(0);
+    }
+    if !owners.is_empty() {
+        let owners: Vec<_> = owners.iter().map(|owner| format!("`{}`", owner)).collect();
+        println!("  keep     {} (shipped by {})", path.display(), owners.join(", "));
+        return Ok(0);
+    }
+    let meta = fs::symlink_metadata(&dest)?;
+    if meta.is_symlink() || meta.is_file() {
+        println!("  delete   {}", path.display());
+        return Ok(meta.len() as i64);
+    }
+    if meta.is_dir() {
+        println!("  delete   {} (if empty)", path.display());
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::tests::{entry, files_table, root};
+
+    fn package(name: &str, depends: &[&str], provides: &[&str]) -> MeowZipMetadata {
+        MeowZipMetadata {
+            name: name.to_owned(),
+            depends: depends.iter().map(|&depend| depend.to_owned()).collect(),
+            provides: provides.iter().map(|&provide| provide.to_owned()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&name| name.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_removal_order() {
+        let pkgmetas = [
+            package("libc", &[], &[]),
+            package("app", &["lib"], &[]),
+            package("lib", &["libc"], &[]),
+        ];
+        assert_eq!(removal_order(&pkgmetas), [1, 2, 0]);
+    }
+
+    #[test]
+    fn test_find_dependants() {
+        let installed = [
+            package("app", &["lib"], &[]),
+            package("lib", &["sh"], &[]),
+            package("bash", &[], &["sh"]),
+            package("zsh", &[], &["sh"]),
+        ];
+        assert_eq!(find_dependants(&installed, &names(&["lib"])), [["app", "lib"]]);
+        // Another provider stays installed
+        assert!(find_dependants(&installed, &names(&["bash"])).is_empty());
+        assert_eq!(find_dependants(&installed, &names(&["bash", "zsh"])), [["lib", "sh"]]);
+        assert!(find_dependants(&installed, &names(&["app"])).is_empty());
+    }
+
+    #[test]
+    fn test_cascade() {
+        let installed = [
+            package("app", &["lib"], &[]),
+            package("lib", &["libc"], &[]),
+            package("libc", &[], &[]),
+            package("tool", &[], &[]),
+        ];
+        let mut removed = names(&["libc"]);
+        assert_eq!(add_dependants(&installed, &mut removed), ["lib", "app"]);
+        assert_eq!(removed, ["libc", "lib", "app"]);
+        assert!(find_dependants(&installed, &removed).is_empty());
+    }
+
+    #[test]
+    fn test_save_path() {
+        let root = root("save-path");
+        let pkgmeta = MeowZipMetadata {
+            name: "foo".to_owned(),
+            backup: vec!["/etc/foo.conf".to_owned()],
+            ..Default::default()
+        };
+        let record = |filepath: &str, skipped: Option<Skipped>| {
+            FileRecord::from(&entry(&root, filepath, "1.0"))
+                .with_package("foo".to_owned())
+                .with_skipped(skipped)
+        };
+        let backup = record("/etc/foo.conf", None);
+        let plain = record("/etc/foo", None);
+        let no_upgrade = record("/etc/kept", Some(Skipped::NoUpgrade));
+        let adopted = record("/etc/adopted", Some(Skipped::Adopted));
+        let files_table = files_table(&[
+            ("/etc/foo.conf", &backup),
+            ("/etc/foo", &plain),
+            ("/etc/kept", &no_upgrade),
+            ("/etc/adopted", &adopted),
+        ]);
+        let save_path = |filepath: &str, content: &str| {
+            fs::write(path_chroot(filepath, &root), content).unwrap();
+            save_path(&root, &pkgmeta, Path::new(filepath), &files_table).unwrap()
+        };
+
+        assert_eq!(save_path("/etc/foo.conf", "1.0"), None);
+        assert_eq!(save_path("/etc/foo.conf", "local"), Some("/etc/foo.conf.meowsave".into()));
+        assert_eq!(save_path("/etc/foo", "local"), None);
+        assert_eq!(save_path("/etc/kept", "1.0"), None);
+        assert_eq!(save_path("/etc/kept", "local"), Some("/etc/kept.meowsave".into()));
+        assert_eq!(save_path("/etc/adopted", "1.0"), Some("/etc/adopted.meowsave".into()));
+
+        // The number goes up while earlier saved copies are still there
+        fs::write(root.join("etc/foo.conf.meowsave"), "older").unwrap();
+        assert_eq!(save_path("/etc/foo.conf", "local"), Some("/etc/foo.conf.meowsave.2".into()));
+    }
+}
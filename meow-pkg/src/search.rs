@@ -0,0 +1,73 @@
+use eyre::Context;
+use libmeow::config::Config;
+use libmeow::meowzip::MeowZipMetadata;
+use libmeow::{LITERAL_STYLE, PLACEHOLDER_STYLE, lock, meowdb, styled, version};
+use redb::{ReadableDatabase, ReadableTable};
+use regex::RegexBuilder;
+
+use crate::install::load_repositories;
+
+pub fn search(pattern: String, refresh: bool, config: &Config) -> eyre::Result<()> {
+    let regex = RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .with_context(|| format!("Invalid search pattern `{}`", pattern))?;
+    let _lock = lock::shared(&config.db_path)?;
+    let db = meowdb::open_read_only(&config.db_path)?;
+    let repos = load_repositories(&db, config, refresh)?;
+    let read_txn = db.begin_read()?;
+    let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
+    let matches = |metadata: &MeowZipMetadata| {
+        regex.is_match(&metadata.name) || regex.is_match(&metadata.description)
+    };
+
+    for (repo, index) in &repos {
+        for package in &index.packages {
+            let metadata = &package.metadata;
+            if !matches(metadata) {
+                continue;
+            }
+            let marker = match pkgs_table.get(metadata.name.as_str())? {
+                None => None,
+                Some(row) => {
+                    let installed = MeowZipMetadata::from(row.value());
+                    let upgradable = version::compare(
+                        &metadata.version,
+                        metadata.release,
+                        &installed.version,
+                        installed.release,
+                    )
+                    .is_gt();
+                    Some(if upgradable { "[upgradable]" } else { "[installed]" })
+                }
+            };
+            print_result(&repo.name, metadata, marker);
+        }
+    }
+    // Installed packages that no repository has anymore
+    for row in pkgs_table.iter()? {
+        let metadata = MeowZipMetadata::from(row?.1.value());
+        if !matches(&metadata) || repos.iter().any(|(_, index)| index.get(&metadata.name).is_some())
+        {
+            continue;
+        }
+        print_result("local", &metadata, Some("[installed]"));
+    }
+    Ok(())
+}
+
+fn print_result(repo: &str, metadata: &MeowZipMetadata, marker: Option<&str>) {
+    print!(
+        "{} {}-{}",
+        styled(&format!("{}/{}", repo, metadata.name), LITERAL_STYLE),
+        metadata.version,
+        metadata.release
+    );
+    match marker {
+        Some(marker) => println!(" {}", styled(marker, PLACEHOLDER_STYLE)),
+        None => println!(),
+    }
+    if !metadata.description.is_empty() {
+        println!("    {}", metadata.description);
+    }
+}
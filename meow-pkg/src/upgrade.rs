@@ -0,0 +1,129 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use eyre::bail;
+use libmeow::config::Config;
+use libmeow::meowzip::MeowZipMetadata;
+use libmeow::repo::{RepoIndex, Repository, find_newest};
+use libmeow::version::Dependency;
+use libmeow::{columned, ensure_superuser, lock, meowdb, version};
+use redb::{ReadableDatabase, ReadableTable};
+
+use crate::cli::InstallOptions;
+use crate::install::{Upgrade, install_locked, load_repositories, record_repositories};
+use crate::warn;
+
+pub fn upgrade(
+    ignore: Vec<String>,
+    options: InstallOptions,
+    root: PathBuf,
+    config: &Config,
+) -> eyre::Result<()> {
+    if !options.dry_run {
+        ensure_superuser()?;
+    }
+    let _lock = if options.dry_run {
+        lock::shared(&config.db_path)?
+    } else {
+        lock::exclusive(&config.db_path, warn)?
+    };
+    let mut installed = vec![];
+    let mut held = vec![];
+    let repos = {
+        let db = meowdb::Db::open(&config.db_path, options.dry_run)?;
+        let repos = load_repositories(&db, config, !options.dry_run)?;
+        if !options.dry_run {
+            record_repositories(&db, &repos)?;
+        }
+        if repos.is_empty() {
+            bail!("No repositories are configured in `{}`", config.path.display());
+        }
+        let read_txn = db.begin_read()?;
+        let pkgs_table = read_txn.open_table(meowdb::PACKAGES)?;
+        let holds_table = meowdb::open_optional_table(&read_txn, meowdb::HOLDS)?;
+        for row in pkgs_table.iter()? {
+            let pkgmeta = MeowZipMetadata::from(row?.1.value());
+            if meowdb::is_held(holds_table.as_ref(), &pkgmeta.name)? {
+                held.push(pkgmeta.name.clone());
+            }
+            installed.push(pkgmeta);
+        }
+        repos
+    };
+
+    // The resolver is told about them too, so that upgrading another package cannot change them
+    let mut frozen = HashMap::new();
+    for pkgmeta in &installed {
+        let reason = if ignore.contains(&pkgmeta.name) {
+            "ignored with `--ignore`".to_owned()
+        } else if config.hold.contains(&pkgmeta.name) {
+            format!("held by `HoldPkg` in `{}`", config.path.display())
+        } else if held.contains(&pkgmeta.name) && !options.ignore_hold {
+            "held, use `--ignore-hold` to upgrade it".to_owned()
+        } else {
+            continue;
+        };
+        frozen.insert(pkgmeta.name.clone(), reason);
+    }
+
+    let mut rows = vec![];
+    let mut targets = vec![];
+    for pkgmeta in &installed {
+        let replacement = find_replacement(&repos, &installed, pkgmeta);
+        let newest = replacement
+            .or_else(|| find_newest(&repos, &pkgmeta.name).map(|(_, package)| &package.metadata));
+        let ordering = newest.map(|newest| {
+            version::compare(&newest.version, newest.release, &pkgmeta.version, pkgmeta.release)
+        });
+        let status = match (newest, ordering) {
+            (None, _) => "not available in any repository".to_owned(),
+            (Some(_), Some(Ordering::Less)) if replacement.is_none() => {
+                "installed version is newer".to_owned()
+            }
+            (Some(_), Some(Ordering::Equal)) if replacement.is_none() => "up to date".to_owned(),
+            _ if frozen.contains_key(&pkgmeta.name) => frozen[&pkgmeta.name].clone(),
+            (Some(newest), _) => {
+                if !targets.contains(&newest.name) {
+                    targets.push(newest.name.clone());
+                }
+                match replacement {
+                    Some(replacement) => format!("replaced by `{}`", replacement.name),
+                    None => "upgrade".to_owned(),
+                }
+            }
+        };
+        rows.push(vec![
+            pkgmeta.name.clone(),
+            format!("{}-{}", pkgmeta.version, pkgmeta.release),
+            newest
+                .map(|newest| format!("{}-{}", newest.version, newest.release))
+                .unwrap_or_else(|| "-".to_owned()),
+            status,
+        ]);
+    }
+    columned::print_table(&["Package", "Installed", "Available", "Status"], &rows);
+    println!();
+    if targets.is_empty() {
+        println!("Nothing to upgrade");
+        return Ok(());
+    }
+    install_locked(&targets, true, Some(&Upgrade { repos, frozen }), &options, &root, config)
+}
+
+/// Finds the newest version of a package that is not installed and replaces `pkgmeta`
+fn find_replacement<'a>(
+    repos: &'a [(Repository, RepoIndex)],
+    installed: &[MeowZipMetadata],
+    pkgmeta: &MeowZipMetadata,
+) -> Option<&'a MeowZipMetadata> {
+    let replacement = repos.iter().flat_map(|(_, index)| &index.packages).find(|package| {
+        !installed.iter().any(|other| other.name == package.metadata.name)
+            && package.metadata.replaces.iter().any(|replace| {
+                let replace = Dependency::parse(replace);
+                replace.name == pkgmeta.name
+                    && replace.satisfied_by(&pkgmeta.version, pkgmeta.release)
+            })
+    })?;
+    find_newest(repos, &replacement.metadata.name).map(|(_, package)| &package.metadata)
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use eyre::Context;
+use libmeow::meowzip::ensure_extension_is_mz;
+use libmeow::repo::{INDEX_FILENAME, RepoIndex, RepoPackage};
+
+pub fn add(repo: PathBuf, packages: Vec<PathBuf>, valid_for: Duration) -> eyre::Result<()> {
+    let index_path = repo.join(INDEX_FILENAME);
+    let mut index =
+        if fs::exists(&index_path)? { RepoIndex::read(&index_path)? } else { RepoIndex::default() };
+    let mut archives = vec![];
+    for path in packages {
+        ensure_extension_is_mz(&path)?;
+        let mut package = RepoPackage::from_archive(&path)?;
+        package.filename = RepoPackage::canonical_filename(&package.metadata);
+        archives.push((path, package));
+    }
+    let mut pruned = vec![];
+    for (path, package) in archives {
+        println!(
+            "Adding {} {}-{}",
+            package.metadata.name, package.metadata.version, package.metadata.release
+        );
+        let dest = repo.join(&package.filename);
+        let old = index.insert(package)?;
+        if fs::canonicalize(&path)? != fs::canonicalize(&repo)?.join(dest.file_name().unwrap()) {
+            fs::copy(&path, &dest).with_context(|| {
+                format!("Failed to copy `{}` to the repository", path.display())
+            })?;
+        }
+        if let Some(old) = old
+            && repo.join(&old.filename) != dest
+        {
+            pruned.push(old.filename);
+        }
+    }
+    // Superseded archives stay until the index no longer lists them
+    index.write(&index_path, valid_for)?;
+    for filename in pruned {
+        println!("Pruning {}", filename);
+        fs::remove_file(repo.join(filename))?;
+    }
+    Ok(())
+}
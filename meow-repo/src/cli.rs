@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+
+use crate::add::add;
+use crate::rebuild::rebuild;
+use crate::remove::remove;
+
+#[derive(Parser)]
+#[command(about = format!("{}{}", libmeow::MEOW, "Package repository manager for meowOS"))]
+#[command(styles=libmeow::CLAP_STYLES)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Number of days the written index stays valid for, clients refuse it once it expires
+    #[arg(long, global = true, value_name = "DAYS", default_value_t = 30)]
+    valid_for: u64,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Add meowzip files to a repository, replacing older versions of the same packages
+    Add {
+        /// Path to the repository directory
+        repo: PathBuf,
+        /// Paths to the meowzip files to add
+        #[arg(required = true)]
+        packages: Vec<PathBuf>,
+    },
+    /// Remove packages from a repository
+    Remove {
+        /// Path to the repository directory
+        repo: PathBuf,
+        /// Names of the packages to remove
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Recreate the index from the meowzip files in a repository, deleting superseded versions
+    Rebuild {
+        /// Path to the repository directory
+        repo: PathBuf,
+    },
+}
+
+pub fn run() -> eyre::Result<()> {
+    let args = Cli::parse();
+    let valid_for = Duration::from_secs(args.valid_for * 24 * 60 * 60);
+    match args.command {
+        Command::Add { repo, packages } => add(repo, packages, valid_for),
+        Command::Remove { repo, packages } => remove(repo, packages, valid_for),
+        Command::Rebuild { repo } => rebuild(repo, valid_for),
+    }
+}
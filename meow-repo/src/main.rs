@@ -0,0 +1,9 @@
+mod add;
+mod cli;
+mod rebuild;
+mod remove;
+
+fn main() -> eyre::Result<()> {
+    color_eyre::install()?;
+    cli::run()
+}
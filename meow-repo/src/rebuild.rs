@@ -0,0 +1,59 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use libmeow::repo::{INDEX_FILENAME, RepoIndex, RepoPackage};
+use libmeow::version;
+
+pub fn rebuild(repo: PathBuf, valid_for: Duration) -> eyre::Result<()> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(&repo)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "mz") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut packages: Vec<RepoPackage> = vec![];
+    let mut superseded = vec![];
+    for path in paths {
+        let package = RepoPackage::from_archive(&path)?;
+        let Some(i) =
+            packages.iter().position(|other| other.metadata.name == package.metadata.name)
+        else {
+            packages.push(package);
+            continue;
+        };
+        let other = &packages[i].metadata;
+        let older = if version::compare(
+            &other.version,
+            other.release,
+            &package.metadata.version,
+            package.metadata.release,
+        )
+        .is_lt()
+        {
+            std::mem::replace(&mut packages[i], package)
+        } else {
+            package
+        };
+        superseded.push(older.filename);
+    }
+    let index_path = repo.join(INDEX_FILENAME);
+    let mut index = RepoIndex::default();
+    // Carry over the generation time so it cannot go backwards with the clock
+    if let Ok(old) = RepoIndex::read(&index_path) {
+        index.generated = old.generated;
+    }
+    for package in packages {
+        index.insert(package)?;
+    }
+    println!("Indexed {} packages", index.packages.len());
+    // Superseded archives stay until the index no longer lists them
+    index.write(&index_path, valid_for)?;
+    for filename in superseded {
+        println!("Pruning {}", filename);
+        fs::remove_file(repo.join(filename))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use eyre::bail;
+use libmeow::repo::{INDEX_FILENAME, RepoIndex};
+
+pub fn remove(repo: PathBuf, packages: Vec<String>, valid_for: Duration) -> eyre::Result<()> {
+    let index_path = repo.join(INDEX_FILENAME);
+    let mut index = RepoIndex::read(&index_path)?;
+    let mut removed = vec![];
+    for name in packages {
+        let Some(package) = index.remove(&name) else {
+            bail!("Package `{}` is not in the repository", name);
+        };
+        removed.push(package);
+    }
+    // Archives are only deleted once the index no longer lists them
+    index.write(&index_path, valid_for)?;
+    for package in removed {
+        println!("Removing {}", package.filename);
+        fs::remove_file(repo.join(package.filename))?;
+    }
+    Ok(())
+}
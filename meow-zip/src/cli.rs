@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 use crate::create::create;
 use crate::extract::extract;
@@ -16,6 +16,44 @@ struct Cli {
     command: Command,
 }
 
+/// Metadata of a package being created
+#[derive(Args)]
+pub struct PackageFields {
+    /// Package name
+    #[arg(short, long)]
+    pub name: String,
+    /// Package version
+    #[arg(short, long)]
+    pub version: String,
+    /// Package release number
+    #[arg(short, long, default_value_t = 1)]
+    pub release: u64,
+    /// Packager name and email
+    #[arg(short, long)]
+    pub packager: String,
+    /// Package license SPDX identifier
+    #[arg(short, long)]
+    pub license: String,
+    /// Dependencies comma separated
+    #[arg(short, long)]
+    pub depends: Option<String>,
+    /// One line description of the package
+    #[arg(long)]
+    pub description: Option<String>,
+    /// Virtual packages provided comma separated, as `name` or `name=version`
+    #[arg(long)]
+    pub provides: Option<String>,
+    /// Packages that cannot be installed at the same time comma separated
+    #[arg(long)]
+    pub conflicts: Option<String>,
+    /// Packages replaced by this one comma separated
+    #[arg(long)]
+    pub replaces: Option<String>,
+    /// Globs of configuration files to keep when modified comma separated, like `/etc/*`
+    #[arg(long)]
+    pub backup: Option<String>,
+}
+
 #[derive(Subcommand)]
 enum Command {
     #[command(alias = "c")]
@@ -23,24 +61,8 @@ enum Command {
     Create {
         /// Path to the meowzip file to create
         file: PathBuf,
-        /// Package name
-        #[arg(short, long)]
-        name: String,
-        /// Package version
-        #[arg(short, long)]
-        version: String,
-        /// Package release number
-        #[arg(short, long)]
-        release: Option<u64>,
-        /// Packager name and email
-        #[arg(short, long)]
-        packager: String,
-        /// Package license SPDX identifier
-        #[arg(short, long)]
-        license: String,
-        /// Dependencies comma separated
-        #[arg(short, long)]
-        depends: Option<String>,
+        #[command(flatten)]
+        fields: Box<PackageFields>,
     },
     #[command(alias = "x")]
     /// Extract all files from a meowzip file
@@ -74,15 +96,7 @@ enum Command {
 
 pub fn run() -> eyre::Result<()> {
     match Cli::parse().command {
-        Command::Create { file, name, version, release, depends, packager, license } => create(
-            file,
-            name,
-            version,
-            release.unwrap_or(1),
-            depends.unwrap_or_default(),
-            packager,
-            license,
-        ),
+        Command::Create { file, fields } => create(file, *fields),
         Command::Extract { file, dir } => extract(file, dir),
         Command::List { file } => list(file),
         Command::Info { file } => info(file),
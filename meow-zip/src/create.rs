@@ -1,21 +1,33 @@
-use std::fs::{self, File, OpenOptions};
+use std::fs::{self, File};
 use std::io::{self, Write};
 use std::os::linux::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
 use eyre::{Context, bail};
+use glob::Pattern;
 use libmeow::meowzip::ensure_extension_is_mz;
-use minisign::SecretKey;
+use libmeow::signature::append_signature;
 
-pub fn create(
-    path: PathBuf,
-    name: String,
-    version: String,
-    release: u64,
-    depends: String,
-    packager: String,
-    license: String,
-) -> eyre::Result<()> {
+use crate::cli::PackageFields;
+
+pub fn create(path: PathBuf, fields: PackageFields) -> eyre::Result<()> {
+    let PackageFields {
+        name,
+        version,
+        release,
+        packager,
+        license,
+        depends,
+        description,
+        provides,
+        conflicts,
+        replaces,
+        backup,
+    } = fields;
+    let depends = depends.unwrap_or_default();
+    for glob in backup.iter().flat_map(|backup| backup.split(',')) {
+        Pattern::new(glob.trim()).with_context(|| format!("Invalid backup glob `{}`", glob))?;
+    }
     if release == 0 {
         bail!("Release number must be greater than 0");
     }
@@ -44,6 +56,17 @@ pub fn create(
     for path in &filelist {
         write_file_entry(&mut file, path)?;
     }
+    for (tag, section) in [
+        (b"desc", description),
+        (b"prov", provides),
+        (b"conf", conflicts),
+        (b"repl", replaces),
+        (b"bkup", backup),
+    ] {
+        if let Some(section) = section.filter(|section| !section.is_empty()) {
+            write_section(&mut file, tag, section.as_bytes())?;
+        }
+    }
     file.write_all(b"ZSTD")?;
     let mut enc = zstd::Encoder::new(file, 0)?;
     for path in filelist {
@@ -66,26 +89,11 @@ fn write_hook(out: &mut File, hook_name: &str) -> eyre::Result<()> {
     Ok(())
 }
 
-fn open_minisign_secret_key() -> eyre::Result<SecretKey> {
-    let Some(home_dir) = dirs::home_dir() else {
-        bail!("Home directory not found");
-    };
-    let skpath = home_dir.join(".minisign/minisign.key");
-    if !fs::exists(&skpath)? {
-        bail!("minisign secret key does not exist. Generate it using `minisign -G`")
-    }
-    SecretKey::from_file(skpath, Some("".to_owned())).context("Failed to open minisign secret key")
-}
-
-fn append_signature(path: &Path) -> eyre::Result<()> {
-    let sk = open_minisign_secret_key()?;
-    let mut file = File::open(path).context("Failed to open meowzip file")?;
-    let sig = minisign::sign(None, &sk, &mut file, None, None)?.to_bytes();
-    let mut file =
-        OpenOptions::new().append(true).open(path).context("Failed to append meowzip file")?;
-    file.write_all(&sig)?;
-    file.write_all(&(sig.len() as u64).to_be_bytes())?;
-    println!();
+/// Writes an optional section, `tag` must be lowercase
+fn write_section(out: &mut File, tag: &[u8; 4], data: &[u8]) -> eyre::Result<()> {
+    out.write_all(tag)?;
+    out.write_all(&(data.len() as u64).to_be_bytes())?;
+    out.write_all(data)?;
     Ok(())
 }
 
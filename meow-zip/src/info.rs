@@ -13,9 +13,14 @@ pub fn info(path: PathBuf) -> eyre::Result<()> {
     let metadata = meowzip::read_metadata(&mut reader)?;
     let total_size: u64 = metadata.filelist.iter().map(|entry| entry.size).sum();
     println!("Name:        {}", metadata.name);
+    println!("Description: {}", metadata.description);
     println!("Version:     {}", metadata.version);
     println!("Release:     {}", metadata.release);
     println!("Depends:     {}", metadata.depends.join(", "));
+    println!("Provides:    {}", metadata.provides.join(", "));
+    println!("Conflicts:   {}", metadata.conflicts.join(", "));
+    println!("Replaces:    {}", metadata.replaces.join(", "));
+    println!("Backup:      {}", metadata.backup.join(", "));
     println!("Packager:    {}", metadata.packager);
     println!("License:     {}", metadata.license);
     println!("Total Files: {}", metadata.filelist.len());
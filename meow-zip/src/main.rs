@@ -1,5 +1,3 @@
-#![feature(seek_stream_len)]
-
 mod cli;
 mod create;
 mod extract;